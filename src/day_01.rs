@@ -81,7 +81,7 @@
 
 const INPUT: &str = include_str!("../input/day_01.txt");
 
-pub fn run() {
+pub fn run(_options: &[String]) {
     let instructions = get_input();
 
     let frequency: i32 = instructions.iter().sum();
@@ -73,7 +73,7 @@ use std::collections::HashMap;
 
 const INPUT: &str = include_str!("../input/day_02.txt");
 
-pub fn run() {
+pub fn run(_options: &[String]) {
     let ids = get_input();
 
     let mut twos = 0;
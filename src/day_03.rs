@@ -72,58 +72,866 @@
 ///
 /// What is the ID of the only claim that doesn't overlap?
 
-use std::collections::HashMap;
+use std::cmp::Reverse;
+use std::collections::{BTreeSet, HashMap};
+use std::fs::File;
+use std::io::{self, BufRead, Write};
+use std::iter;
 use regex::Regex;
 
 const INPUT: &str = include_str!("../input/day_03.txt");
 
-pub fn run() {
-    let input = get_input();
+/// Side of the square fabric sheet, in inches.
+const SHEET_SIZE: Coordinate = 1000;
+/// Largest fabric side that is still printed as ASCII art.
+const ASCII_LIMIT: Coordinate = 80;
+/// Most square inches a heatmap is rendered for, four times the sheet. Claims
+/// far away from the sheet would otherwise need a huge, mostly empty image.
+const RENDER_LIMIT: Coordinate = 4 * SHEET_SIZE * SHEET_SIZE;
 
-    let mut fabric_map = HashMap::new();
-    let mut ids = Vec::new();
+/// Claims are stored as 64-bit coordinates, but have to lie within the 32-bit
+/// range. That way adding or subtracting any two coordinates can't overflow.
+type Coordinate = i64;
+type Point = (Coordinate, Coordinate);
+type FabricMap = HashMap<Point, Vec<u16>>;
+/// For every claim, the claims it overlaps with and by how many square inches.
+type ConflictGraph = HashMap<u16, HashMap<u16, usize>>;
 
-    for (id, start_x, start_y, width, height) in input {
-        ids.push(id);
-        for x in start_x..start_x+width {
-            for y in start_y..start_y+height {
-                (*fabric_map.entry((x, y)).or_insert(Vec::new())).push(id);
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+struct Claim {
+    id: u16,
+    x: Coordinate,
+    y: Coordinate,
+    width: Coordinate,
+    height: Coordinate,
+}
+
+/// The fabric with all its claims. The overlap and the set of intact claims
+/// are kept up to date as claims are added and withdrawn, so a change only
+/// costs as much as the area of the claim involved.
+struct Fabric {
+    claims: HashMap<u16, Claim>,
+    map: FabricMap,
+    overlap: usize,
+    contested: HashMap<u16, usize>,
+    intact: BTreeSet<u16>,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+struct Rect {
+    x: Coordinate,
+    y: Coordinate,
+    width: Coordinate,
+    height: Coordinate,
+}
+
+/// A quadtree over the claims. Every claim lives in the deepest node whose
+/// quadrant fully contains it.
+struct FabricIndex {
+    bounds: Rect,
+    claims: Vec<Claim>,
+    children: Vec<FabricIndex>,
+}
+
+pub fn run(options: &[String]) {
+    let claims = parse_input(INPUT).unwrap();
+    let mut fabric = Fabric::from_claims(&claims);
+    let fabric_map = &fabric.map;
+
+    println!("Amount of overlap in the fabric plan is: {}", fabric.overlap);
+
+    let conflict_graph = create_conflict_graph(&claims, fabric_map);
+    let intact = intact_claims(&conflict_graph);
+    println!("Found the non-overlapping claims, they're: {:?}", intact);
+
+    if let Some((id, conflicts)) = most_contested_claim(&conflict_graph) {
+        println!("The most contested claim is {}, it conflicts with {} other claims",
+                 id, conflicts.len());
+    }
+
+    let sheet = Rect {x:0, y:0, width:SHEET_SIZE, height:SHEET_SIZE};
+    match largest_free_rect(fabric_map, &sheet) {
+        Some(free) => println!("The largest unclaimed piece of fabric is {},{}: {}x{}",
+                               free.x, free.y, free.width, free.height),
+        None => println!("There is no unclaimed fabric left on the sheet"),
+    }
+
+    let bounds = fabric_bounds(&claims);
+    if bounds.width <= ASCII_LIMIT && bounds.height <= ASCII_LIMIT {
+        print!("{}", render_ascii(fabric_map, &bounds));
+    }
+
+    let options: Vec<&str> = options.iter().map(String::as_str).collect();
+    match options.as_slice() {
+        [] => {},
+        ["render", path] => {
+            match render_ppm(fabric_map, &bounds.union(&sheet), &intact) {
+                Ok(image) => {
+                    File::create(path)
+                        .and_then(|mut file| file.write_all(&image))
+                        .unwrap();
+                    println!("Wrote the fabric heatmap to {}", path);
+                },
+                Err(error) => println!("Can't render the fabric: {}", error),
             }
+        },
+        ["conflicts"] => print!("{}", conflict_report(&conflict_graph)),
+        ["query", queries @ ..] => {
+            let index = FabricIndex::new(&claims, bounds);
+            if queries.is_empty() {
+                let stdin = io::stdin();
+                for line in stdin.lock().lines() {
+                    println!("{}", answer_query(&index, &line.unwrap()));
+                }
+            } else {
+                for query in queries {
+                    println!("{}", answer_query(&index, query));
+                }
+            }
+        },
+        ["withdraw", ids @ ..] => {
+            for id in ids {
+                match id.parse() {
+                    Ok(id) => match fabric.remove(id) {
+                        Some(_) => println!("After withdrawing claim {} the overlap is {} and the intact claims are {:?}",
+                                            id, fabric.overlap, fabric.intact),
+                        None => println!("Can't withdraw claim {}, there is no such claim", id),
+                    },
+                    Err(error) => println!("Can't use '{}' as a claim ID: {}", id, error),
+                }
+            }
+        },
+        other => println!("Unknown options for day_03: {:?}", other),
+    }
+}
+
+impl Fabric {
+    fn new() -> Fabric {
+        Fabric {
+            claims: HashMap::new(),
+            map: HashMap::new(),
+            overlap: 0,
+            contested: HashMap::new(),
+            intact: BTreeSet::new(),
+        }
+    }
+
+    fn from_claims(claims: &[Claim]) -> Fabric {
+        let mut fabric = Fabric::new();
+        for claim in claims {
+            fabric.add(*claim);
         }
+        fabric
+    }
+
+    /// Places a claim on the fabric, replacing an earlier claim with the same
+    /// ID.
+    fn add(&mut self, claim: Claim) {
+        self.remove(claim.id);
+
+        let mut contested = 0;
+        for x in claim.x..claim.x+claim.width {
+            for y in claim.y..claim.y+claim.height {
+                let ids = self.map.entry((x, y)).or_default();
+                match ids.as_slice() {
+                    [] => {},
+                    [other] => {
+                        self.overlap += 1;
+                        let other_contested = self.contested.get_mut(other).unwrap();
+                        *other_contested += 1;
+                        if *other_contested == 1 {
+                            self.intact.remove(other);
+                        }
+                        contested += 1;
+                    },
+                    _ => contested += 1,
+                }
+                ids.push(claim.id);
+            }
+        }
+
+        if contested == 0 {
+            self.intact.insert(claim.id);
+        }
+        self.contested.insert(claim.id, contested);
+        self.claims.insert(claim.id, claim);
+    }
+
+    /// Withdraws the claim with the given ID, if there is one.
+    fn remove(&mut self, id: u16) -> Option<Claim> {
+        let claim = self.claims.remove(&id)?;
+
+        for x in claim.x..claim.x+claim.width {
+            for y in claim.y..claim.y+claim.height {
+                let ids = self.map.get_mut(&(x, y)).unwrap();
+                let position = ids.iter().position(|other| *other == id).unwrap();
+                ids.swap_remove(position);
+                match ids.as_slice() {
+                    [] => {
+                        self.map.remove(&(x, y));
+                    },
+                    [other] => {
+                        self.overlap -= 1;
+                        let other_contested = self.contested.get_mut(other).unwrap();
+                        *other_contested -= 1;
+                        if *other_contested == 0 {
+                            self.intact.insert(*other);
+                        }
+                    },
+                    _ => {},
+                }
+            }
+        }
+
+        self.contested.remove(&id);
+        self.intact.remove(&id);
+        Some(claim)
+    }
+}
+
+fn create_conflict_graph(claims: &[Claim], fabric_map: &FabricMap) -> ConflictGraph {
+    let mut graph: ConflictGraph = claims.iter()
+        .map(|claim| (claim.id, HashMap::new()))
+        .collect();
+    for ids in fabric_map.values().filter(|ids| ids.len() > 1) {
+        for (a, b) in iproduct!(ids.iter(), ids.iter()).filter(|(a, b)| a != b) {
+            *graph.get_mut(a).unwrap().entry(*b).or_insert(0) += 1;
+        }
+    }
+    graph
+}
+
+/// All claims that don't overlap with any other claim, sorted by ID.
+fn intact_claims(graph: &ConflictGraph) -> Vec<u16> {
+    let mut intact: Vec<_> = graph.iter()
+        .filter(|(_, conflicts)| conflicts.is_empty())
+        .map(|(id, _)| *id)
+        .collect();
+    intact.sort();
+    intact
+}
+
+/// The claim that conflicts with the most other claims. Ties go to the claim
+/// with the most contested square inches, and then to the lowest ID.
+fn most_contested_claim(graph: &ConflictGraph) -> Option<(u16, &HashMap<u16, usize>)> {
+    graph.iter()
+        .filter(|(_, conflicts)| !conflicts.is_empty())
+        .max_by_key(|(id, conflicts)| {
+            (conflicts.len(), conflicts.values().sum::<usize>(), Reverse(**id))
+        })
+        .map(|(id, conflicts)| (*id, conflicts))
+}
+
+/// One line per claim listing the claims it conflicts with, as
+/// `#id: #other (square inches), ...`.
+fn conflict_report(graph: &ConflictGraph) -> String {
+    let mut ids: Vec<_> = graph.keys().collect();
+    ids.sort();
+
+    let mut report = String::new();
+    for id in ids {
+        let mut conflicts: Vec<_> = graph[id].iter().collect();
+        conflicts.sort();
+        let conflicts: Vec<_> = conflicts.iter()
+            .map(|(other, inches)| format!("#{} ({})", other, inches))
+            .collect();
+        report.push_str(&format!("#{}: {}\n", id, conflicts.join(", ")));
     }
+    report
+}
+
+impl Claim {
+    fn rect(&self) -> Rect {
+        Rect {x:self.x, y:self.y, width:self.width, height:self.height}
+    }
+}
+
+impl Rect {
+    /// Builds a rectangle, checking that it lies within the 32-bit range.
+    fn checked(x: &str, y: &str, width: &str, height: &str) -> Result<Rect, String> {
+        let parse = |number: &str| number.parse::<i32>()
+            .map_err(|error| format!("Can't use '{}' as a coordinate: {}", number, error));
+        let (x, y, width, height) = (parse(x)?, parse(y)?, parse(width)?, parse(height)?);
+        if x.checked_add(width).is_none() || y.checked_add(height).is_none() {
+            return Err(format!("{},{}: {}x{} extends beyond the fabric", x, y, width, height));
+        }
+        Ok(Rect {
+            x: x as Coordinate,
+            y: y as Coordinate,
+            width: width as Coordinate,
+            height: height as Coordinate,
+        })
+    }
+
+    fn union(&self, other: &Rect) -> Rect {
+        let x = self.x.min(other.x);
+        let y = self.y.min(other.y);
+        let x_end = (self.x + self.width).max(other.x + other.width);
+        let y_end = (self.y + self.height).max(other.y + other.height);
+        Rect {x, y, width:x_end - x, height:y_end - y}
+    }
+
+    fn contains(&self, other: &Rect) -> bool {
+        self.x <= other.x && other.x + other.width <= self.x + self.width &&
+            self.y <= other.y && other.y + other.height <= self.y + self.height
+    }
+
+    fn intersection(&self, other: &Rect) -> Option<Rect> {
+        let x = self.x.max(other.x);
+        let y = self.y.max(other.y);
+        let x_end = (self.x + self.width).min(other.x + other.width);
+        let y_end = (self.y + self.height).min(other.y + other.height);
+        if x < x_end && y < y_end {
+            Some(Rect {x, y, width:x_end - x, height:y_end - y})
+        } else {
+            None
+        }
+    }
+
+    fn quadrants(&self) -> Vec<Rect> {
+        let left = self.width / 2;
+        let top = self.height / 2;
+        vec![
+            Rect {x:self.x, y:self.y, width:left, height:top},
+            Rect {x:self.x + left, y:self.y, width:self.width - left, height:top},
+            Rect {x:self.x, y:self.y + top, width:left, height:self.height - top},
+            Rect {x:self.x + left, y:self.y + top, width:self.width - left, height:self.height - top},
+        ]
+    }
+}
+
+impl FabricIndex {
+    fn new(claims: &[Claim], bounds: Rect) -> FabricIndex {
+        let mut index = FabricIndex {bounds, claims: Vec::new(), children: Vec::new()};
+        for claim in claims {
+            index.insert(*claim);
+        }
+        index
+    }
+
+    fn insert(&mut self, claim: Claim) {
+        if self.children.is_empty() && self.bounds.width > 1 && self.bounds.height > 1 {
+            self.children = self.bounds.quadrants().into_iter()
+                .map(|bounds| FabricIndex {bounds, claims: Vec::new(), children: Vec::new()})
+                .collect();
+        }
+        match self.children.iter_mut().find(|child| child.bounds.contains(&claim.rect())) {
+            Some(child) => child.insert(claim),
+            None => self.claims.push(claim),
+        }
+    }
+
+    /// The IDs of all claims covering the given square inch, sorted.
+    fn claims_at(&self, point: &Point) -> Vec<u16> {
+        let mut ids = Vec::new();
+        self.visit(&Rect {x:point.0, y:point.1, width:1, height:1}, &mut |claim| ids.push(claim.id));
+        ids.sort();
+        ids
+    }
+
+    /// The amount of square inches within the rectangle that are claimed by
+    /// two or more claims.
+    fn overlap_in(&self, rect: &Rect) -> usize {
+        let mut counts = HashMap::new();
+        self.visit(rect, &mut |claim| {
+            let covered = claim.rect().intersection(rect).unwrap();
+            for x in covered.x..covered.x+covered.width {
+                for y in covered.y..covered.y+covered.height {
+                    *counts.entry((x, y)).or_insert(0) += 1;
+                }
+            }
+        });
+        counts.values().filter(|&&count| count > 1).count()
+    }
+
+    /// Calls `f` for every claim intersecting the rectangle.
+    fn visit<F: FnMut(&Claim)>(&self, rect: &Rect, f: &mut F) {
+        if self.bounds.intersection(rect).is_none() {
+            return;
+        }
+        for claim in self.claims.iter().filter(|claim| claim.rect().intersection(rect).is_some()) {
+            f(claim);
+        }
+        for child in self.children.iter() {
+            child.visit(rect, f);
+        }
+    }
+}
+
+/// Answers a query of the form `x,y` (who claims this inch) or `x,y: wxh`
+/// (how much of this rectangle is contested).
+fn answer_query(index: &FabricIndex, query: &str) -> String {
+    lazy_static! {
+        static ref RE: Regex = Regex::new(r"^\s*(-?\d+),(-?\d+)(?::\s*(\d+)x(\d+))?\s*$").unwrap();
+    }
+    let captures = match RE.captures(query) {
+        Some(captures) => captures,
+        None => return format!("Can't understand the query '{}'", query),
+    };
+    let number = |i: usize| captures.get(i).map_or("1", |m| m.as_str());
+    let rect = match Rect::checked(number(1), number(2), number(3), number(4)) {
+        Ok(rect) => rect,
+        Err(error) => return error,
+    };
+    match captures.get(3) {
+        Some(_) => format!("{} square inches of {},{}: {}x{} are contested",
+                           index.overlap_in(&rect), rect.x, rect.y, rect.width, rect.height),
+        None => format!("Inch {},{} is claimed by {:?}",
+                        rect.x, rect.y, index.claims_at(&(rect.x, rect.y))),
+    }
+}
+
+/// Finds the largest rectangle on the sheet that isn't claimed by anyone. Every
+/// row is treated as a histogram of how many unclaimed inches are stacked on
+/// top of each column, of which the largest rectangle is found with a stack.
+/// Ties go to the top-most and then left-most rectangle.
+fn largest_free_rect(fabric_map: &FabricMap, sheet: &Rect) -> Option<Rect> {
+    let mut heights = vec![0; sheet.width as usize];
+    let mut largest: Option<Rect> = None;
+    let mut largest_area = 0;
+
+    for y in sheet.y..sheet.y+sheet.height {
+        for (i, height) in heights.iter_mut().enumerate() {
+            let x = sheet.x + i as Coordinate;
+            *height = if fabric_map.contains_key(&(x, y)) { 0 } else { *height + 1 };
+        }
+
+        let mut stack: Vec<(usize, Coordinate)> = Vec::new();
+        for (i, &height) in heights.iter().chain(iter::once(&0)).enumerate() {
+            let mut start = i;
+            while let Some(&(bar_start, bar_height)) = stack.last() {
+                if bar_height < height {
+                    break;
+                }
+                stack.pop();
+                let width = (i - bar_start) as Coordinate;
+                let found = Rect {
+                    x: sheet.x + bar_start as Coordinate,
+                    y: y - bar_height + 1,
+                    width,
+                    height: bar_height,
+                };
+                // Rectangles are found at the row of their bottom edge, so an
+                // equally large one found later can still be higher up.
+                let area = width * bar_height;
+                let better = area > largest_area || (area == largest_area && area > 0 &&
+                    largest.is_some_and(|largest| (found.y, found.x) < (largest.y, largest.x)));
+                if better {
+                    largest_area = area;
+                    largest = Some(found);
+                }
+                start = bar_start;
+            }
+            stack.push((start, height));
+        }
+    }
+    largest
+}
+
+/// The smallest fabric, including 0,0, that contains all claims with a margin
+/// of one inch on the right and bottom, like in the example above.
+fn fabric_bounds(claims: &[Claim]) -> Rect {
+    let origin = Rect {x:0, y:0, width:0, height:0};
+    let bounds = claims.iter()
+        .fold(origin, |bounds, claim| bounds.union(&claim.rect()));
+    Rect {width:bounds.width + 1, height:bounds.height + 1, ..bounds}
+}
 
-    let overlap = fabric_map.values()
-        .filter(|claims| claims.len() > 1)
-        .count();
+/// Draws the fabric the way the puzzle does: the ID for inches with a single
+/// claim (`#` if the ID doesn't fit in one character), `X` for overlaps and
+/// `.` for unclaimed fabric.
+fn render_ascii(fabric_map: &FabricMap, bounds: &Rect) -> String {
+    let mut output = String::new();
+    for y in bounds.y..bounds.y+bounds.height {
+        for x in bounds.x..bounds.x+bounds.width {
+            let c = match fabric_map.get(&(x, y)).map(|claims| claims.as_slice()) {
+                None | Some([]) => '.',
+                Some([id]) if *id < 10 => (b'0' + *id as u8) as char,
+                Some([_]) => '#',
+                Some(_) => 'X',
+            };
+            output.push(c);
+        }
+        output.push('\n');
+    }
+    output
+}
 
-    println!("Amount of overlap in the fabric plan is: {}", overlap);
+/// Encodes the fabric as a binary PPM heatmap of the amount of claims per
+/// inch, with the intact claims drawn in green. Bounds covering more than
+/// `RENDER_LIMIT` square inches are refused.
+fn render_ppm(fabric_map: &FabricMap, bounds: &Rect, intact: &[u16]) -> Result<Vec<u8>, String> {
+    // Sides can be close to 2^32 inches, so the area itself can overflow.
+    if bounds.width.checked_mul(bounds.height).is_none_or(|area| area > RENDER_LIMIT) {
+        return Err(format!("{}x{} inches is too large for a heatmap, the limit is {} square inches",
+                           bounds.width, bounds.height, RENDER_LIMIT));
+    }
+    let max_claims = fabric_map.values()
+        .map(|claims| claims.len())
+        .max()
+        .unwrap_or(0);
 
-    for id in ids.iter() {
-        let overlap = fabric_map.values()
-            .filter(|claims| claims.contains(&id))
-            .any(|claims| claims.len() > 1);
-        if !overlap {
-            println!("Found the non-overlapping claim, it's: {}", id);
-            break;
+    let mut image = format!("P6\n{} {}\n255\n", bounds.width, bounds.height).into_bytes();
+    for y in bounds.y..bounds.y+bounds.height {
+        for x in bounds.x..bounds.x+bounds.width {
+            let claims = fabric_map.get(&(x, y)).map_or(&[][..], |claims| claims.as_slice());
+            let colour = match claims {
+                [id] if intact.contains(id) => [0, 200, 0],
+                _ => heat_colour(claims.len(), max_claims),
+            };
+            image.extend_from_slice(&colour);
         }
     }
+    Ok(image)
+}
+
+/// Black for unclaimed fabric, blue for single claims, going through red to
+/// yellow for the most contested inches.
+fn heat_colour(claims: usize, max_claims: usize) -> [u8; 3] {
+    match claims {
+        0 => [0, 0, 0],
+        1 => [0, 0, 160],
+        _ => {
+            let heat = 255 * (claims - 1) / (max_claims - 1);
+            [255, heat as u8, 0]
+        },
+    }
 }
 
-fn get_input() -> Vec<(u16, u16, u16, u16, u16)> {
-    let re = Regex::new(r"(\d+) @ (\d+),(\d+): (\d+)x(\d+)").unwrap();
+fn parse_input(input: &str) -> Result<Vec<Claim>, String> {
+    let re = Regex::new(r"(\d+) @ (-?\d+),(-?\d+): (\d+)x(\d+)").unwrap();
 
-    re.captures_iter(INPUT)
+    re.captures_iter(input)
         .filter_map(|cap| {
             let groups = (cap.get(1), cap.get(2), cap.get(3), cap.get(4), cap.get(5));
             match groups {
-                (Some(id), Some(start_x), Some(start_y), Some(width), Some(height)) =>
-                    Some((id.as_str().parse().unwrap(),
-                          start_x.as_str().parse().unwrap(),
-                          start_y.as_str().parse().unwrap(),
-                          width.as_str().parse().unwrap(),
-                          height.as_str().parse().unwrap())),
+                (Some(id), Some(x), Some(y), Some(width), Some(height)) =>
+                    Some(convert_claim(id.as_str(), x.as_str(), y.as_str(),
+                                       width.as_str(), height.as_str())),
                 _ => None,
             }
         })
         .collect()
 }
+
+fn convert_claim(id: &str, x: &str, y: &str, width: &str, height: &str) -> Result<Claim, String> {
+    let id = id.parse()
+        .map_err(|error| format!("Can't use '{}' as a claim ID: {}", id, error))?;
+    let Rect {x, y, width, height} = Rect::checked(x, y, width, height)
+        .map_err(|error| format!("Claim #{}: {}", id, error))?;
+    Ok(Claim {id, x, y, width, height})
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const EXAMPLE: &str =
+        "#1 @ 1,3: 4x4\n\
+        #2 @ 3,1: 4x4\n\
+        #3 @ 5,5: 2x2";
+
+    #[test]
+    fn test_parse_input() {
+        let output = vec![
+            Claim {id:1, x:1, y:3, width:4, height:4},
+            Claim {id:2, x:3, y:1, width:4, height:4},
+            Claim {id:3, x:5, y:5, width:2, height:2}];
+
+        assert_eq!(parse_input(EXAMPLE).unwrap(), output);
+    }
+
+    #[test]
+    fn test_parse_input_negative_offsets() {
+        let input = "#1 @ -3,-2: 5x4\n#2 @ 2147483000,-2147483648: 600x1";
+        let output = vec![
+            Claim {id:1, x:-3, y:-2, width:5, height:4},
+            Claim {id:2, x:2_147_483_000, y:-2_147_483_648, width:600, height:1}];
+
+        assert_eq!(parse_input(input), Ok(output));
+    }
+
+    #[test]
+    fn test_parse_input_overflow() {
+        assert_eq!(parse_input("#1 @ 2147483000,0: 700x1"),
+                   Err("Claim #1: 2147483000,0: 700x1 extends beyond the fabric".to_string()));
+        assert!(parse_input("#1 @ 0,99999999999: 1x1").is_err());
+        assert!(parse_input("#70000 @ 0,0: 1x1").is_err());
+    }
+
+    #[test]
+    fn test_fabric_bounds() {
+        let claims = parse_input("#1 @ -3,2: 2x2\n#2 @ 4,-5: 1x1").unwrap();
+
+        assert_eq!(fabric_bounds(&claims), Rect {x:-3, y:-5, width:9, height:10});
+    }
+
+    #[test]
+    fn test_render_ascii_negative_offsets() {
+        let claims = parse_input("#1 @ -2,-1: 2x2\n#2 @ -1,0: 2x2").unwrap();
+        let fabric_map = Fabric::from_claims(&claims).map;
+
+        let output =
+            "11..\n\
+            1X2.\n\
+            .22.\n\
+            ....\n";
+
+        assert_eq!(render_ascii(&fabric_map, &fabric_bounds(&claims)), output);
+    }
+
+    #[test]
+    fn test_fabric_from_claims() {
+        let fabric = Fabric::from_claims(&parse_input(EXAMPLE).unwrap());
+
+        assert_eq!(fabric.overlap, 4);
+        assert_eq!(fabric.intact, vec![3].into_iter().collect());
+    }
+
+    #[test]
+    fn test_fabric_add() {
+        let mut fabric = Fabric::from_claims(&parse_input(EXAMPLE).unwrap());
+        fabric.add(Claim {id:4, x:6, y:6, width:2, height:2});
+
+        assert_eq!(fabric.overlap, 5);
+        assert!(fabric.intact.is_empty());
+        assert_eq!(fabric.contested[&3], 1);
+        assert_eq!(fabric.contested[&4], 1);
+    }
+
+    #[test]
+    fn test_fabric_add_replaces() {
+        let mut fabric = Fabric::from_claims(&parse_input(EXAMPLE).unwrap());
+        fabric.add(Claim {id:2, x:3, y:1, width:1, height:1});
+
+        assert_eq!(fabric.overlap, 0);
+        assert_eq!(fabric.intact, vec![1, 2, 3].into_iter().collect());
+    }
+
+    #[test]
+    fn test_fabric_remove() {
+        let mut fabric = Fabric::from_claims(&parse_input(EXAMPLE).unwrap());
+
+        assert_eq!(fabric.remove(1), Some(Claim {id:1, x:1, y:3, width:4, height:4}));
+        assert_eq!(fabric.overlap, 0);
+        assert_eq!(fabric.intact, vec![2, 3].into_iter().collect());
+        assert_eq!(fabric.map.len(), 16 + 4);
+
+        assert_eq!(fabric.remove(1), None);
+    }
+
+    #[test]
+    fn test_fabric_remove_triple_overlap() {
+        let mut fabric = Fabric::from_claims(&parse_input("#1 @ 0,0: 2x2\n#2 @ 1,1: 2x2\n#3 @ 1,1: 1x1").unwrap());
+        assert_eq!(fabric.overlap, 1);
+
+        fabric.remove(2);
+        assert_eq!(fabric.overlap, 1);
+        assert!(fabric.intact.is_empty());
+
+        fabric.remove(3);
+        assert_eq!(fabric.overlap, 0);
+        assert_eq!(fabric.intact, vec![1].into_iter().collect());
+    }
+
+    #[test]
+    fn test_create_conflict_graph() {
+        let claims = parse_input(EXAMPLE).unwrap();
+        let fabric_map = Fabric::from_claims(&claims).map;
+
+        let mut output = HashMap::new();
+        output.insert(1, vec![(2, 4)].into_iter().collect());
+        output.insert(2, vec![(1, 4)].into_iter().collect());
+        output.insert(3, HashMap::new());
+
+        assert_eq!(create_conflict_graph(&claims, &fabric_map), output);
+    }
+
+    #[test]
+    fn test_intact_claims() {
+        let claims = parse_input("#4 @ 0,0: 1x1\n#1 @ 5,5: 2x2\n#2 @ 6,6: 2x2\n#3 @ 9,9: 1x1").unwrap();
+        let graph = create_conflict_graph(&claims, &Fabric::from_claims(&claims).map);
+
+        assert_eq!(intact_claims(&graph), vec![3, 4]);
+    }
+
+    #[test]
+    fn test_most_contested_claim() {
+        let claims = parse_input("#1 @ 0,0: 3x3\n#2 @ 2,2: 2x2\n#3 @ 0,0: 1x1\n#4 @ 9,9: 1x1").unwrap();
+        let graph = create_conflict_graph(&claims, &Fabric::from_claims(&claims).map);
+
+        let (id, conflicts) = most_contested_claim(&graph).unwrap();
+        assert_eq!(id, 1);
+        assert_eq!(conflicts.len(), 2);
+    }
+
+    #[test]
+    fn test_most_contested_claim_tie() {
+        let claims = parse_input(EXAMPLE).unwrap();
+        let graph = create_conflict_graph(&claims, &Fabric::from_claims(&claims).map);
+
+        assert_eq!(most_contested_claim(&graph).map(|(id, _)| id), Some(1));
+    }
+
+    #[test]
+    fn test_conflict_report() {
+        let claims = parse_input(EXAMPLE).unwrap();
+        let graph = create_conflict_graph(&claims, &Fabric::from_claims(&claims).map);
+
+        let output =
+            "#1: #2 (4)\n\
+            #2: #1 (4)\n\
+            #3: \n";
+
+        assert_eq!(conflict_report(&graph), output);
+    }
+
+    #[test]
+    fn test_rect_intersection() {
+        let a = Rect {x:1, y:3, width:4, height:4};
+        let b = Rect {x:3, y:1, width:4, height:4};
+        let c = Rect {x:5, y:5, width:2, height:2};
+
+        assert_eq!(a.intersection(&b), Some(Rect {x:3, y:3, width:2, height:2}));
+        assert_eq!(b.intersection(&a), Some(Rect {x:3, y:3, width:2, height:2}));
+        assert_eq!(a.intersection(&c), None);
+    }
+
+    #[test]
+    fn test_claims_at() {
+        let claims = parse_input(EXAMPLE).unwrap();
+        let index = FabricIndex::new(&claims, Rect {x:0, y:0, width:8, height:8});
+
+        assert_eq!(index.claims_at(&(0, 0)), vec![]);
+        assert_eq!(index.claims_at(&(1, 3)), vec![1]);
+        assert_eq!(index.claims_at(&(4, 4)), vec![1, 2]);
+        assert_eq!(index.claims_at(&(6, 6)), vec![3]);
+    }
+
+    #[test]
+    fn test_overlap_in() {
+        let claims = parse_input(EXAMPLE).unwrap();
+        let index = FabricIndex::new(&claims, Rect {x:0, y:0, width:8, height:8});
+
+        assert_eq!(index.overlap_in(&Rect {x:0, y:0, width:8, height:8}), 4);
+        assert_eq!(index.overlap_in(&Rect {x:4, y:4, width:3, height:3}), 1);
+        assert_eq!(index.overlap_in(&Rect {x:5, y:5, width:2, height:2}), 0);
+    }
+
+    #[test]
+    fn test_answer_query() {
+        let claims = parse_input(EXAMPLE).unwrap();
+        let index = FabricIndex::new(&claims, Rect {x:0, y:0, width:8, height:8});
+
+        assert_eq!(answer_query(&index, "3,4"), "Inch 3,4 is claimed by [1, 2]");
+        assert_eq!(answer_query(&index, "0,0: 4x4"), "1 square inches of 0,0: 4x4 are contested");
+        assert_eq!(answer_query(&index, "nonsense"), "Can't understand the query 'nonsense'");
+        assert_eq!(answer_query(&index, "-1,-1: 2x2"), "0 square inches of -1,-1: 2x2 are contested");
+        assert_eq!(answer_query(&index, "2147483647,0: 2x2"), "2147483647,0: 2x2 extends beyond the fabric");
+    }
+
+    #[test]
+    fn test_largest_free_rect() {
+        let claims = parse_input(EXAMPLE).unwrap();
+        let fabric_map = Fabric::from_claims(&claims).map;
+        let sheet = Rect {x:0, y:0, width:8, height:8};
+
+        assert_eq!(largest_free_rect(&fabric_map, &sheet), Some(Rect {x:0, y:0, width:3, height:3}));
+    }
+
+    #[test]
+    fn test_largest_free_rect_in_the_middle() {
+        let claims = parse_input("#1 @ 0,0: 6x1\n#2 @ 0,1: 1x4\n#3 @ 4,2: 2x3\n#4 @ 1,4: 3x1").unwrap();
+        let fabric_map = Fabric::from_claims(&claims).map;
+        let sheet = Rect {x:0, y:0, width:6, height:5};
+
+        assert_eq!(largest_free_rect(&fabric_map, &sheet), Some(Rect {x:1, y:1, width:3, height:3}));
+    }
+
+    #[test]
+    fn test_largest_free_rect_tie() {
+        // A 1x4 strip on the left and a 4x1 strip further down on the right.
+        let claims = parse_input("#1 @ 1,0: 1x4\n#2 @ 2,0: 4x1\n#3 @ 2,2: 4x2").unwrap();
+        let fabric_map = Fabric::from_claims(&claims).map;
+        let sheet = Rect {x:0, y:0, width:6, height:4};
+
+        assert_eq!(largest_free_rect(&fabric_map, &sheet), Some(Rect {x:0, y:0, width:1, height:4}));
+
+        // Two 2x1 strips on the same row go to the left-most one.
+        let claims = parse_input("#1 @ 2,0: 1x1").unwrap();
+        let fabric_map = Fabric::from_claims(&claims).map;
+        let sheet = Rect {x:0, y:0, width:5, height:1};
+
+        assert_eq!(largest_free_rect(&fabric_map, &sheet), Some(Rect {x:0, y:0, width:2, height:1}));
+    }
+
+    #[test]
+    fn test_largest_free_rect_full_sheet() {
+        let claims = parse_input("#1 @ 0,0: 2x2\n#2 @ 1,1: 3x3").unwrap();
+        let fabric_map = Fabric::from_claims(&claims).map;
+
+        assert_eq!(largest_free_rect(&fabric_map, &Rect {x:0, y:0, width:2, height:2}), None);
+        assert_eq!(largest_free_rect(&fabric_map, &Rect {x:-2, y:0, width:2, height:2}),
+                   Some(Rect {x:-2, y:0, width:2, height:2}));
+    }
+
+    #[test]
+    fn test_render_ascii() {
+        let claims = parse_input(EXAMPLE).unwrap();
+        let fabric_map = Fabric::from_claims(&claims).map;
+        let bounds = fabric_bounds(&claims);
+
+        let output =
+            "........\n\
+            ...2222.\n\
+            ...2222.\n\
+            .11XX22.\n\
+            .11XX22.\n\
+            .111133.\n\
+            .111133.\n\
+            ........\n";
+
+        assert_eq!(render_ascii(&fabric_map, &bounds), output);
+    }
+
+    #[test]
+    fn test_render_ppm() {
+        let claims = parse_input(EXAMPLE).unwrap();
+        let fabric_map = Fabric::from_claims(&claims).map;
+        let image = render_ppm(&fabric_map, &Rect {x:0, y:0, width:8, height:8}, &[3]).unwrap();
+
+        let header = b"P6\n8 8\n255\n";
+        assert_eq!(&image[..header.len()], header);
+        assert_eq!(image.len(), header.len() + 8 * 8 * 3);
+
+        let pixel = |x: usize, y: usize| {
+            let offset = header.len() + (y * 8 + x) * 3;
+            &image[offset..offset+3]
+        };
+        assert_eq!(pixel(0, 0), [0, 0, 0]);
+        assert_eq!(pixel(1, 3), [0, 0, 160]);
+        assert_eq!(pixel(3, 3), [255, 255, 0]);
+        assert_eq!(pixel(5, 5), [0, 200, 0]);
+    }
+
+    #[test]
+    fn test_render_ppm_too_large() {
+        let claims = parse_input("#1 @ 2000000000,0: 1x1").unwrap();
+        let fabric_map = Fabric::from_claims(&claims).map;
+        let sheet = Rect {x:0, y:0, width:SHEET_SIZE, height:SHEET_SIZE};
+
+        assert!(render_ppm(&fabric_map, &fabric_bounds(&claims).union(&sheet), &[1]).is_err());
+        assert!(render_ppm(&fabric_map, &sheet, &[1]).is_ok());
+    }
+
+    #[test]
+    fn test_render_ppm_full_range() {
+        let claims = parse_input("#1 @ -2147483648,-2147483648: 1x1\n\
+                                  #2 @ 2147483000,2147483000: 1x1").unwrap();
+        let fabric_map = Fabric::from_claims(&claims).map;
+        let bounds = fabric_bounds(&claims);
+
+        assert!(bounds.width.checked_mul(bounds.height).is_none());
+        assert!(render_ppm(&fabric_map, &bounds, &[1, 2]).is_err());
+    }
+}
@@ -93,132 +93,1247 @@
 /// What is the ID of the guard you chose multiplied by the minute you chose?
 /// (In the above example, the answer would be 99 * 45 = 4455.)
 
-use chrono::{NaiveDateTime, Timelike};
+use chrono::{Duration, NaiveDate, NaiveDateTime, NaiveTime, Timelike};
 use regex::Regex;
-use std::collections::HashMap;
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap, HashSet};
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader};
+use std::path::Path;
 
 const INPUT: &str = include_str!("../input/day_04.txt");
 
-#[derive(Debug, PartialEq, Eq, PartialOrd, Ord)]
+const MINUTES_PER_DAY: i64 = 24 * 60;
+/// The most guards a generated log can have.
+const MAX_GENERATED_GUARDS: usize = 1_000_000;
+/// How many records a streamed log can be out of order by, by default.
+const REORDER_BUFFER: usize = 64;
+
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone, Copy)]
 enum Event {
     GuardStarts(u32),
     FallsAsleep,
     WakesUp,
 }
 
-pub fn run() {
-    let input = get_input();
+type Record = (NaiveDateTime, Event);
+
+/// Where in the log a record was read from.
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone)]
+struct Source {
+    line: usize,
+    text: String,
+}
+
+/// A record together with the log line it was read from.
+type Entry = (Record, Source);
+/// For every guard, how often they were asleep on each minute of the window.
+type SleepMap = HashMap<u32, Vec<u32>>;
+
+/// The part of every night that is being observed: `minutes` long, beginning
+/// at `start`. The puzzle only observes the midnight hour.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+struct Window {
+    start: NaiveTime,
+    minutes: u32,
+}
+
+/// A guard asleep from `start` up to, but not including, `end`.
+#[derive(Debug, PartialEq, Eq, Clone)]
+struct Nap {
+    start: NaiveDateTime,
+    end: NaiveDateTime,
+}
+
+/// The naps of the guard on duty for the night of `date`.
+#[derive(Debug, PartialEq, Eq, Clone)]
+struct Shift {
+    guard: u32,
+    date: NaiveDate,
+    naps: Vec<Nap>,
+}
+
+/// An inconsistency in the log, together with the entry it was found on.
+#[derive(Debug, PartialEq, Eq)]
+enum Anomaly {
+    NoGuardOnDuty(Entry),
+    FallsAsleepTwice(Entry),
+    WakesWithoutSleeping(Entry),
+    ShiftEndsAsleep(Entry),
+    OutOfOrder(Entry),
+}
+
+/// Turns the sorted log into shifts one record at a time, collecting anomalies
+/// instead of guessing what was meant.
+#[derive(Default)]
+struct ShiftBuilder {
+    shift: Option<Shift>,
+    fell_asleep: Option<Entry>,
+    anomalies: Vec<Anomaly>,
+}
+
+/// Which variations on the `[%Y-%m-%d %H:%M] message` log format to accept.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+struct LogFormat {
+    /// Timestamps with seconds, like `[1518-11-01 00:05:30]`.
+    seconds: bool,
+    /// ISO timestamps, like `[1518-11-01T00:05]`.
+    iso_separator: bool,
+    /// Messages in any case, like `guard #10 begins shift`.
+    ignore_case: bool,
+    /// Trailing comments starting with `//` or `# `.
+    comments: bool,
+}
+
+/// Which guard is on duty on which night.
+type Roster = Vec<(NaiveDate, u32)>;
+
+/// A small xorshift random number generator, so generated logs only depend on
+/// their seed.
+struct Random {
+    state: u64,
+}
+
+/// What kind of log to generate. Guards nap between `naps.0` and `naps.1`
+/// times a night, each nap lasting between `nap_length.0` and `nap_length.1`
+/// minutes.
+#[derive(Debug, Clone, Copy)]
+struct GeneratorConfig {
+    seed: u64,
+    guards: usize,
+    nights: usize,
+    naps: (u32, u32),
+    nap_length: (u32, u32),
+}
+
+/// A shuffled log in the puzzle format, with the answers the strategies should
+/// give for it, as worked out while generating it.
+struct GeneratedLog {
+    lines: Vec<String>,
+    sleep_map: SleepMap,
+    strategy_1: Option<Choice>,
+    strategy_2: Option<Choice>,
+}
+
+/// A line of the log that couldn't be turned into a record, with its line
+/// number.
+type Unrecognised = (usize, String);
+
+/// Reads a log as it's being written, for example from a tailed file. Records
+/// are held in a reorder buffer, and once it holds more than `capacity` records
+/// the earliest one is final. Records arriving after a later record was made
+/// final can't be placed anymore and are reported as anomalies.
+struct LogStream {
+    buffer: BinaryHeap<Reverse<Entry>>,
+    capacity: usize,
+    last_final: Option<Record>,
+    builder: ShiftBuilder,
+    window: Window,
+    shifts: Vec<Shift>,
+    sleep_map: SleepMap,
+}
+
+/// Everything a strategy can base its choice on.
+struct SleepData {
+    shifts: Vec<Shift>,
+    sleep_map: SleepMap,
+    window: Window,
+}
+
+/// The guard and minute picked by a strategy, and the score it picked them by.
+/// The minute is given as an offset into the observation window; see
+/// `Window::clock_minute` for the minute of the hour.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+struct Choice {
+    guard: u32,
+    window_offset: usize,
+    score: u32,
+}
+
+trait Strategy {
+    /// The name to select the strategy by on the command line.
+    fn name(&self) -> &'static str;
+
+    /// Every guard and minute the strategy would consider, with their score.
+    fn candidates(&self, data: &SleepData) -> Vec<Choice>;
+
+    /// The candidates sharing the highest score. Ties are broken by picking the
+    /// lowest guard ID and then the earliest minute, so the first candidate is
+    /// the strategy's choice and the rest are ordered the same way.
+    fn choose(&self, data: &SleepData) -> Vec<Choice> {
+        let candidates = self.candidates(data);
+        let best_score = match candidates.iter().map(|choice| choice.score).max() {
+            Some(score) => score,
+            None => return Vec::new(),
+        };
+        let mut best: Vec<_> = candidates.into_iter()
+            .filter(|choice| choice.score == best_score)
+            .collect();
+        best.sort_by_key(|choice| (choice.guard, choice.window_offset));
+        best
+    }
+}
+
+/// Statistics on the lengths of naps, in minutes.
+#[derive(Debug, PartialEq)]
+struct NapStats {
+    count: usize,
+    mean: f64,
+    median: f64,
+    longest: i64,
+}
+
+/// Which shifts to show in the sleep chart.
+#[derive(Debug, Default)]
+struct ChartFilter {
+    guard: Option<u32>,
+    from: Option<NaiveDate>,
+    to: Option<NaiveDate>,
+}
+
+/// Strategy 1: the guard with the most minutes asleep, on their most slept
+/// minute. Scores the total minutes asleep.
+struct MostMinutesAsleep;
+
+/// Strategy 2: the guard most frequently asleep on the same minute. Scores the
+/// times asleep on that minute.
+struct MostFrequentMinute;
+
+/// The guard asleep on their most slept minute during the largest share of
+/// their shifts. Scores that share as a percentage.
+struct MostConsistentSleeper;
+
+/// The guard whose naps start the latest on average, on that average minute.
+/// Scores the average minute.
+struct LatestAverageNap;
+
+/// The guard that slept on the most different days, on their most slept
+/// minute. Scores the amount of days.
+struct MostDistinctDays;
+
+pub fn run(options: &[String]) {
+    let mut window = Window::default();
+    let mut strategies = Vec::new();
+    let mut filter = ChartFilter::default();
+    let mut show_chart = false;
+    let mut show_histogram = false;
+    let mut exports = Vec::new();
+    let mut stream = None;
+    let mut reorder_buffer = REORDER_BUFFER;
+    let mut format = LogFormat::lenient();
+    let mut predictions = Vec::new();
+    let mut generate = None;
+    let mut generate_naps = None;
+    let mut generate_nap_length = None;
+
+    let mut options = options.iter();
+    while let Some(option) = options.next() {
+        match option.as_str() {
+            "window" => {
+                let start = options.next().expect("window needs a start time, like 23:30");
+                let minutes = options.next().expect("window needs a length in minutes");
+                match Window::new(start, minutes) {
+                    Ok(new_window) => window = new_window,
+                    Err(error) => println!("Can't use that window, keeping the default: {}", error),
+                }
+            },
+            "strategy" => {
+                let name = options.next().expect("strategy needs a name");
+                match find_strategy(name) {
+                    Some(strategy) => strategies.push(strategy),
+                    None => println!("Unknown strategy {}, pick one of {:?}", name,
+                                     all_strategies().iter().map(|s| s.name()).collect::<Vec<_>>()),
+                }
+            },
+            "chart" => show_chart = true,
+            "histogram" => show_histogram = true,
+            "export" => {
+                let format = options.next().expect("export needs a format, csv or json");
+                let path = options.next().expect("export needs a path to write to");
+                exports.push((format.clone(), path.clone()));
+            },
+            "guard" => {
+                let guard = options.next().expect("guard needs an ID");
+                filter.guard = Some(guard.trim_start_matches('#').parse().unwrap());
+            },
+            "stream" => stream = Some(options.next().expect("stream needs a file, or - for stdin").clone()),
+            "generate" => {
+                let mut number = |name| options.next()
+                    .unwrap_or_else(|| panic!("generate needs a {}", name))
+                    .parse::<u64>().unwrap();
+                generate = Some(GeneratorConfig {
+                    seed: number("seed"),
+                    guards: number("guard count") as usize,
+                    nights: number("night count") as usize,
+                    ..GeneratorConfig::default()
+                });
+            },
+            "naps" | "nap-length" => {
+                let mut number = || options.next()
+                    .unwrap_or_else(|| panic!("{} needs a minimum and a maximum", option))
+                    .parse::<u32>().unwrap();
+                let range = (number(), number());
+                if option == "naps" {
+                    generate_naps = Some(range);
+                } else {
+                    generate_nap_length = Some(range);
+                }
+            },
+            "predict" => {
+                let roster = options.next().expect("predict needs a roster file");
+                let length = options.next().expect("predict needs a length in minutes");
+                predictions.push((roster.clone(), length.parse::<usize>().unwrap()));
+            },
+            "buffer" => reorder_buffer = options.next().expect("buffer needs a size").parse().unwrap(),
+            "format" => match options.next().map(String::as_str) {
+                Some("strict") => format = LogFormat::strict(),
+                Some("lenient") => format = LogFormat::lenient(),
+                other => println!("Unknown log format {:?}, pick strict or lenient", other),
+            },
+            "from" => filter.from = Some(parse_date(options.next().expect("from needs a date"))),
+            "to" => filter.to = Some(parse_date(options.next().expect("to needs a date"))),
+            other => println!("Unknown option for day_04: {}", other),
+        }
+    }
+
+    if let Some(mut config) = generate {
+        config.naps = generate_naps.unwrap_or(config.naps);
+        config.nap_length = generate_nap_length.unwrap_or(config.nap_length);
+        let log = match generate_log(&config) {
+            Ok(log) => log,
+            Err(error) => {
+                println!("Can't generate a log: {}", error);
+                return;
+            },
+        };
+        for line in log.lines.iter() {
+            println!("{}", line);
+        }
+        let sleepers = log.sleep_map.values().filter(|asleep| asleep.iter().any(|&n| n > 0)).count();
+        eprintln!("{} of {} guards slept on the job", sleepers, log.sleep_map.len());
+        for (name, choice) in [("1", log.strategy_1), ("2", log.strategy_2)].iter() {
+            match choice {
+                Some(Choice {guard, window_offset, ..}) =>
+                    eprintln!("Strategy {} should pick guard {} on minute {}", name, guard,
+                              Window::default().clock_minute(*window_offset)),
+                None => eprintln!("Strategy {} should find that no guard slept", name),
+            }
+        }
+        return;
+    }
+
+    let (shifts, anomalies, sleep_map, unrecognised) = match stream {
+        Some(path) => {
+            let mut log = LogStream::new(reorder_buffer, window);
+            let unrecognised = if path == "-" {
+                let stdin = io::stdin();
+                log.read_from(stdin.lock(), &format).unwrap()
+            } else {
+                log.read_from(BufReader::new(File::open(path).unwrap()), &format).unwrap()
+            };
+            let (shifts, anomalies, sleep_map) = log.finish();
+            (shifts, anomalies, sleep_map, unrecognised)
+        },
+        None => {
+            let (input, unrecognised) = get_input(&format);
+            let (shifts, anomalies) = build_shifts(&input);
+            let sleep_map = guards_sleep_map(&shifts, &window);
+            (shifts, anomalies, sleep_map, unrecognised)
+        },
+    };
+    if !unrecognised.is_empty() {
+        println!("Couldn't make sense of {} lines in the guard log:", unrecognised.len());
+        for (number, line) in unrecognised.iter() {
+            println!("line {}: {}", number, line);
+        }
+    }
+    if !anomalies.is_empty() {
+        println!("Found {} anomalies in the guard log:", anomalies.len());
+        for anomaly in anomalies.iter() {
+            println!("{}", anomaly);
+        }
+    }
+
+    let data = SleepData {shifts, sleep_map, window};
+
+    if strategies.is_empty() {
+        strategies = vec![Box::new(MostMinutesAsleep), Box::new(MostFrequentMinute)];
+    }
+    for strategy in strategies.iter() {
+        println!("Strategy {}:", strategy.name());
+        let choices = strategy.choose(&data);
+        match choices.split_first() {
+            Some((choice, ties)) => {
+                println!("The sleepiest guard is {}", choice.guard);
+                println!("The sleepiest minute is {} (scoring {})",
+                         data.window.clock_minute(choice.window_offset), choice.score);
+                println!("making the solution be {}", choice.answer(&data.window));
+                for tie in ties {
+                    println!("Tied with guard {} on minute {}",
+                             tie.guard, data.window.clock_minute(tie.window_offset));
+                }
+            },
+            None => println!("No guard was ever asleep"),
+        }
+    }
 
-    let sleep_map = guards_sleep_map(input);
+    if show_chart {
+        print!("{}", render_chart(&data.shifts, &data.window, &filter));
+    }
+    if show_histogram {
+        let mut guards: Vec<_> = data.sleep_map.keys()
+            .filter(|guard| filter.guard.is_none_or(|filtered| filtered == **guard))
+            .collect();
+        guards.sort();
+        for guard in guards {
+            print!("{}", render_histogram(*guard, &data.sleep_map[guard], &data.window));
+        }
+    }
+
+    for (format, path) in exports.iter() {
+        match format.as_str() {
+            "csv" => {
+                fs::create_dir_all(path).unwrap();
+                for (name, contents) in export_csv(&data) {
+                    fs::write(Path::new(path).join(name), contents).unwrap();
+                }
+            },
+            "json" => fs::write(path, export_json(&data)).unwrap(),
+            other => {
+                println!("Unknown export format {}, pick csv or json", other);
+                continue;
+            },
+        }
+        println!("Exported the guard statistics as {} to {}", format, path);
+    }
+
+    for (path, length) in predictions.iter() {
+        let roster = fs::read_to_string(path)
+            .map_err(|error| format!("Can't read {}: {}", path, error))
+            .and_then(|contents| parse_roster(&contents));
+        let roster = match roster {
+            Ok(roster) => roster,
+            Err(error) => {
+                println!("Skipping the roster {}: {}", path, error);
+                continue;
+            },
+        };
+        for (date, guard) in roster {
+            print!("{} #{}: ", date, guard);
+            let best = predict_asleep(&data, guard)
+                .and_then(|probabilities| best_window(&probabilities, *length));
+            match best {
+                Some((start, chance)) => {
+                    let time = data.window.clock_time(start);
+                    println!("the best {} minutes start at {}, the guard is asleep {:.0}% of \
+                             that time", length, time.format("%H:%M"), chance * 100.0);
+                },
+                None => println!("no prediction, the guard hasn't been seen for long enough"),
+            }
+        }
+    }
+}
+
+impl Choice {
+    /// The puzzle answer: the guard ID multiplied by the minute of the hour.
+    fn answer(&self, window: &Window) -> u32 {
+        self.guard * window.clock_minute(self.window_offset)
+    }
+}
 
-    strategy_1(&sleep_map);
-    strategy_2(&sleep_map);
+fn all_strategies() -> Vec<Box<dyn Strategy>> {
+    vec![
+        Box::new(MostMinutesAsleep),
+        Box::new(MostFrequentMinute),
+        Box::new(MostConsistentSleeper),
+        Box::new(LatestAverageNap),
+        Box::new(MostDistinctDays),
+    ]
 }
 
-fn strategy_1(sleep_map: &HashMap<u32, [u32; 60]>) {
-    println!("Strategy 1:");
-    let (sleepiest_guard, _) = sleep_map.iter()
-        .max_by_key(|(_, asleep)| asleep.iter().sum::<u32>())
-        .unwrap();
-    println!("The sleepiest guard is {}", sleepiest_guard);
+fn find_strategy(name: &str) -> Option<Box<dyn Strategy>> {
+    all_strategies().into_iter()
+        .find(|strategy| strategy.name() == name)
+}
 
-    let (sleepiest_minute, _) = sleep_map[sleepiest_guard].iter()
+/// The minutes a guard was asleep on most often, and how often that was. No
+/// minutes are returned for a guard that never slept.
+fn sleepiest_minutes(asleep: &[u32]) -> (Vec<usize>, u32) {
+    let most_slept = asleep.iter().cloned().max().unwrap_or(0);
+    if most_slept == 0 {
+        return (Vec::new(), 0);
+    }
+    let minutes = asleep.iter()
         .enumerate()
-        .max_by_key(|(_, times_slept)| *times_slept)
-        .unwrap();
-    println!("The sleepiest minute is {}", sleepiest_minute);
+        .filter(|(_, &times_slept)| times_slept == most_slept)
+        .map(|(minute, _)| minute)
+        .collect();
+    (minutes, most_slept)
+}
+
+/// A candidate for every sleepiest minute of the guard, all with the same
+/// score.
+fn sleepiest_minute_candidates(guard: u32, asleep: &[u32], score: u32) -> Vec<Choice> {
+    let (minutes, _) = sleepiest_minutes(asleep);
+    minutes.into_iter()
+        .map(|window_offset| Choice {guard, window_offset, score})
+        .collect()
+}
+
+impl Strategy for MostMinutesAsleep {
+    fn name(&self) -> &'static str {
+        "most-asleep"
+    }
+
+    fn candidates(&self, data: &SleepData) -> Vec<Choice> {
+        data.sleep_map.iter()
+            .flat_map(|(guard, asleep)| {
+                sleepiest_minute_candidates(*guard, asleep, asleep.iter().sum())
+            })
+            .collect()
+    }
+}
+
+impl Strategy for MostFrequentMinute {
+    fn name(&self) -> &'static str {
+        "same-minute"
+    }
+
+    fn candidates(&self, data: &SleepData) -> Vec<Choice> {
+        data.sleep_map.iter()
+            .flat_map(|(guard, asleep)| {
+                let (_, times_slept) = sleepiest_minutes(asleep);
+                sleepiest_minute_candidates(*guard, asleep, times_slept)
+            })
+            .collect()
+    }
+}
+
+impl Strategy for MostConsistentSleeper {
+    fn name(&self) -> &'static str {
+        "consistent"
+    }
 
-    let solution = sleepiest_guard * (sleepiest_minute as u32);
-    println!("making the solution be {}", solution);
+    fn candidates(&self, data: &SleepData) -> Vec<Choice> {
+        let mut shift_counts = HashMap::new();
+        for shift in data.shifts.iter() {
+            *shift_counts.entry(shift.guard).or_insert(0) += 1;
+        }
+        data.sleep_map.iter()
+            .flat_map(|(guard, asleep)| {
+                let (_, times_slept) = sleepiest_minutes(asleep);
+                let score = 100 * times_slept / shift_counts[guard];
+                sleepiest_minute_candidates(*guard, asleep, score)
+            })
+            .collect()
+    }
+}
+
+impl Strategy for LatestAverageNap {
+    fn name(&self) -> &'static str {
+        "latest-nap"
+    }
+
+    fn candidates(&self, data: &SleepData) -> Vec<Choice> {
+        let mut nap_starts: HashMap<u32, Vec<usize>> = HashMap::new();
+        for shift in data.shifts.iter() {
+            let starts = nap_starts.entry(shift.guard).or_default();
+            starts.extend(shift.naps.iter().filter_map(|nap| data.window.minute_of(&nap.start)));
+        }
+        nap_starts.iter()
+            .filter(|(_, starts)| !starts.is_empty())
+            .map(|(guard, starts)| {
+                let window_offset = starts.iter().sum::<usize>() / starts.len();
+                Choice {guard: *guard, window_offset, score: window_offset as u32}
+            })
+            .collect()
+    }
+}
+
+impl Strategy for MostDistinctDays {
+    fn name(&self) -> &'static str {
+        "distinct-days"
+    }
+
+    fn candidates(&self, data: &SleepData) -> Vec<Choice> {
+        let mut days: HashMap<u32, HashSet<NaiveDate>> = HashMap::new();
+        for shift in data.shifts.iter().filter(|shift| !shift.naps.is_empty()) {
+            days.entry(shift.guard).or_default().insert(shift.date);
+        }
+        days.iter()
+            .flat_map(|(guard, days)| {
+                sleepiest_minute_candidates(*guard, &data.sleep_map[guard], days.len() as u32)
+            })
+            .collect()
+    }
 }
 
-fn strategy_2(sleep_map: &HashMap<u32, [u32; 60]>) {
-    println!("Strategy 2:");
-    let (sleepiest_guard, sleepiest_minute, _) = sleep_map.iter()
-        .map(|(guard, asleep)| {
-            let (most_slept_minute, amount_slept) = asleep.iter()
+/// Counts every minute a guard was asleep during the observation window. Naps
+/// are followed minute by minute, so they can run past the end of the hour,
+/// past midnight or even span multiple nights.
+fn guards_sleep_map(shifts: &[Shift], window: &Window) -> SleepMap {
+    let mut sleep_map = HashMap::new();
+    for shift in shifts {
+        add_to_sleep_map(&mut sleep_map, shift, window);
+    }
+    sleep_map
+}
+
+fn add_to_sleep_map(sleep_map: &mut SleepMap, shift: &Shift, window: &Window) {
+    let asleep = sleep_map.entry(shift.guard)
+        .or_insert_with(|| vec![0; window.minutes as usize]);
+    for nap in shift.naps.iter() {
+        for i in nap.minutes_in(window) {
+            asleep[i] += 1;
+        }
+    }
+}
+
+impl LogStream {
+    fn new(capacity: usize, window: Window) -> LogStream {
+        LogStream {
+            buffer: BinaryHeap::new(),
+            capacity,
+            last_final: None,
+            builder: ShiftBuilder::default(),
+            window,
+            shifts: Vec::new(),
+            sleep_map: HashMap::new(),
+        }
+    }
+
+    /// Reads records until the reader runs out, returning the lines that
+    /// couldn't be parsed.
+    fn read_from<R: BufRead>(&mut self, reader: R, format: &LogFormat) -> io::Result<Vec<Unrecognised>> {
+        let mut unrecognised = Vec::new();
+        for (i, line) in reader.lines().enumerate() {
+            let line = line?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            match convert_line(&line, format) {
+                Some(record) => self.push((record, Source {line: i + 1, text: line})),
+                None => unrecognised.push((i + 1, line)),
+            }
+        }
+        Ok(unrecognised)
+    }
+
+    fn push(&mut self, entry: Entry) {
+        // Compare whole records, the same order the buffer and sorted logs use.
+        let (record, _) = entry;
+        if self.last_final.is_some_and(|last_final| record < last_final) {
+            self.builder.anomalies.push(Anomaly::OutOfOrder(entry));
+            return;
+        }
+        self.buffer.push(Reverse(entry));
+        while self.buffer.len() > self.capacity {
+            let Reverse(entry) = self.buffer.pop().unwrap();
+            self.make_final(entry);
+        }
+    }
+
+    fn make_final(&mut self, entry: Entry) {
+        let (record, _) = entry;
+        self.last_final = Some(record);
+        if let Some(shift) = self.builder.push(entry) {
+            add_to_sleep_map(&mut self.sleep_map, &shift, &self.window);
+            self.shifts.push(shift);
+        }
+    }
+
+    /// Makes everything still in the buffer final and ends the last shift.
+    fn finish(mut self) -> (Vec<Shift>, Vec<Anomaly>, SleepMap) {
+        while let Some(Reverse(entry)) = self.buffer.pop() {
+            self.make_final(entry);
+        }
+        if let Some(shift) = self.builder.finish() {
+            add_to_sleep_map(&mut self.sleep_map, &shift, &self.window);
+            self.shifts.push(shift);
+        }
+        (self.shifts, self.builder.anomalies, self.sleep_map)
+    }
+}
+
+impl Nap {
+    /// The minutes of the window the guard was asleep on during this nap, once
+    /// for every night the nap lasted.
+    fn minutes_in(&self, window: &Window) -> Vec<usize> {
+        let mut minutes = Vec::new();
+        let mut minute = self.start;
+        while minute < self.end {
+            minutes.extend(window.minute_of(&minute));
+            minute += Duration::minutes(1);
+        }
+        minutes
+    }
+}
+
+impl ChartFilter {
+    fn matches(&self, shift: &Shift) -> bool {
+        self.guard.is_none_or(|guard| guard == shift.guard) &&
+            self.from.is_none_or(|from| from <= shift.date) &&
+            self.to.is_none_or(|to| shift.date <= to)
+    }
+}
+
+/// Draws the shifts like the puzzle does, with a row per shift showing the
+/// minutes the guard was awake as `.` and asleep as `#`.
+fn render_chart(shifts: &[Shift], window: &Window, filter: &ChartFilter) -> String {
+    let shifts: Vec<_> = shifts.iter()
+        .filter(|shift| filter.matches(shift))
+        .collect();
+    let ids: Vec<_> = shifts.iter()
+        .map(|shift| format!("#{}", shift.guard))
+        .collect();
+    let id_width = ids.iter().map(|id| id.len()).max().unwrap_or(0).max(2);
+
+    let mut chart = format!("Date   {:<width$}  Minute\n", "ID", width = id_width);
+    chart.push_str(&minute_header(window, 7 + id_width + 2));
+    for (shift, id) in shifts.iter().zip(ids.iter()) {
+        let mut asleep = vec!['.'; window.minutes as usize];
+        for i in shift.naps.iter().flat_map(|nap| nap.minutes_in(window)) {
+            asleep[i] = '#';
+        }
+        chart.push_str(&format!("{}  {:<width$}  {}\n", shift.date.format("%m-%d"), id,
+                                asleep.iter().collect::<String>(), width = id_width));
+    }
+    chart
+}
+
+/// Draws how often a guard was asleep on every minute, as a bar chart with a
+/// column per minute.
+fn render_histogram(guard: u32, asleep: &[u32], window: &Window) -> String {
+    let mut histogram = format!("Guard #{}, asleep for {} minutes\n",
+                                guard, asleep.iter().sum::<u32>());
+    let highest = asleep.iter().cloned().max().unwrap_or(0);
+    for level in (1..=highest).rev() {
+        let bars: String = asleep.iter()
+            .map(|&times_slept| if times_slept >= level { '#' } else { '.' })
+            .collect();
+        histogram.push_str(&format!("{:>5}  {}\n", level, bars));
+    }
+    histogram.push_str(&minute_header(window, 7));
+    histogram
+}
+
+/// The two header rows numbering the minutes of the window, with the ten's
+/// digit in the first row and the one's digit in the second.
+fn minute_header(window: &Window, indent: usize) -> String {
+    let minutes: Vec<_> = (0..window.minutes as usize)
+        .map(|i| window.clock_minute(i))
+        .collect();
+    let tens: String = minutes.iter().map(|m| (b'0' + (m / 10) as u8) as char).collect();
+    let ones: String = minutes.iter().map(|m| (b'0' + (m % 10) as u8) as char).collect();
+    format!("{:indent$}{}\n{:indent$}{}\n", "", tens, "", ones, indent = indent)
+}
+
+impl Nap {
+    fn length(&self) -> i64 {
+        (self.end - self.start).num_minutes()
+    }
+}
+
+fn nap_stats(naps: &[&Nap]) -> Option<NapStats> {
+    let mut lengths: Vec<_> = naps.iter().map(|nap| nap.length()).collect();
+    if lengths.is_empty() {
+        return None;
+    }
+    lengths.sort();
+    let count = lengths.len();
+    let median = if count % 2 == 0 {
+        (lengths[count / 2 - 1] + lengths[count / 2]) as f64 / 2.0
+    } else {
+        lengths[count / 2] as f64
+    };
+    Some(NapStats {
+        count,
+        mean: lengths.iter().sum::<i64>() as f64 / count as f64,
+        median,
+        longest: lengths[count - 1],
+    })
+}
+
+/// The guards in the data sorted by ID, with their shifts.
+fn shifts_per_guard(data: &SleepData) -> Vec<(u32, Vec<&Shift>)> {
+    let mut guards: Vec<_> = data.sleep_map.keys().cloned().collect();
+    guards.sort();
+    guards.into_iter()
+        .map(|guard| (guard, data.shifts.iter().filter(|shift| shift.guard == guard).collect()))
+        .collect()
+}
+
+fn all_naps<'a>(shifts: &[&'a Shift]) -> Vec<&'a Nap> {
+    shifts.iter()
+        .flat_map(|shift| shift.naps.iter())
+        .collect()
+}
+
+/// The CSV files to export, by file name: per guard totals, per minute counts,
+/// every nap and the summary over all guards.
+fn export_csv(data: &SleepData) -> Vec<(&'static str, String)> {
+    let stats_columns = |stats: Option<NapStats>| match stats {
+        Some(stats) => format!("{},{},{}", stats.mean, stats.median, stats.longest),
+        None => ",,".to_string(),
+    };
+
+    let mut guards = String::from("guard,shifts,naps,minutes_asleep,mean_nap,median_nap,longest_nap\n");
+    let mut minutes = String::from("guard");
+    for i in 0..data.window.minutes as usize {
+        minutes.push_str(&format!(",{}", data.window.clock_time(i).format("%H:%M")));
+    }
+    minutes.push('\n');
+
+    for (guard, shifts) in shifts_per_guard(data) {
+        let naps = all_naps(&shifts);
+        let asleep = &data.sleep_map[&guard];
+        guards.push_str(&format!("{},{},{},{},{}\n", guard, shifts.len(), naps.len(),
+                                 asleep.iter().sum::<u32>(), stats_columns(nap_stats(&naps))));
+
+        let counts: Vec<_> = asleep.iter().map(|times_slept| times_slept.to_string()).collect();
+        minutes.push_str(&format!("{},{}\n", guard, counts.join(",")));
+    }
+
+    let mut naps = String::from("guard,date,start,end,minutes\n");
+    for shift in data.shifts.iter() {
+        for nap in shift.naps.iter() {
+            naps.push_str(&format!("{},{},{},{},{}\n", shift.guard, shift.date,
+                                   nap.start.format("%Y-%m-%d %H:%M"),
+                                   nap.end.format("%Y-%m-%d %H:%M"), nap.length()));
+        }
+    }
+
+    let shifts: Vec<_> = data.shifts.iter().collect();
+    let summary = format!("guards,shifts,naps,mean_nap,median_nap,longest_nap\n{},{},{},{}\n",
+                          data.sleep_map.len(), shifts.len(), all_naps(&shifts).len(),
+                          stats_columns(nap_stats(&all_naps(&shifts))));
+
+    vec![
+        ("guards.csv", guards),
+        ("minutes.csv", minutes),
+        ("naps.csv", naps),
+        ("summary.csv", summary),
+    ]
+}
+
+/// The same statistics as the CSV export, as a single JSON document.
+fn export_json(data: &SleepData) -> String {
+    let stats_object = |stats: Option<NapStats>| match stats {
+        Some(stats) => format!("{{\"count\": {}, \"mean\": {}, \"median\": {}, \"longest\": {}}}",
+                               stats.count, stats.mean, stats.median, stats.longest),
+        None => "null".to_string(),
+    };
+    let nap_object = |nap: &Nap| format!(
+        "{{\"start\": \"{}\", \"end\": \"{}\", \"minutes\": {}}}",
+        nap.start.format("%Y-%m-%d %H:%M"), nap.end.format("%Y-%m-%d %H:%M"), nap.length());
+
+    let guards: Vec<_> = shifts_per_guard(data).into_iter()
+        .map(|(guard, shifts)| {
+            let naps = all_naps(&shifts);
+            let asleep = &data.sleep_map[&guard];
+            let counts: Vec<_> = asleep.iter()
                 .enumerate()
-                .max_by_key(|(_, times_slept)| *times_slept)
-                .unwrap();
-            (guard, most_slept_minute, amount_slept)
+                .map(|(i, times_slept)| {
+                    format!("\"{}\": {}", data.window.clock_time(i).format("%H:%M"), times_slept)
+                })
+                .collect();
+            format!("    {{\"guard\": {}, \"shifts\": {}, \"minutes_asleep\": {}, \
+                     \"naps\": {}, \"minutes\": {{{}}}}}",
+                    guard, shifts.len(), asleep.iter().sum::<u32>(),
+                    stats_object(nap_stats(&naps)), counts.join(", "))
+        })
+        .collect();
+
+    let shifts: Vec<_> = data.shifts.iter()
+        .map(|shift| {
+            let naps: Vec<_> = shift.naps.iter().map(nap_object).collect();
+            format!("    {{\"guard\": {}, \"date\": \"{}\", \"naps\": [{}]}}",
+                    shift.guard, shift.date, naps.join(", "))
         })
-        .max_by_key(|(_, _, times_slept)| *times_slept)
-        .unwrap();
-    println!("The sleepiest guard is {}", sleepiest_guard);
-    println!("The sleepiest minute is {}", sleepiest_minute);
+        .collect();
 
-    let solution = sleepiest_guard * (sleepiest_minute as u32);
-    println!("making the solution be {}", solution);
+    let all_shifts: Vec<_> = data.shifts.iter().collect();
+    format!("{{\n  \"guards\": [\n{}\n  ],\n  \"shifts\": [\n{}\n  ],\n  \"summary\": {}\n}}\n",
+            guards.join(",\n"), shifts.join(",\n"),
+            stats_object(nap_stats(&all_naps(&all_shifts))))
 }
 
-fn guards_sleep_map(input: Vec<(NaiveDateTime, Event)>) -> HashMap<u32, [u32; 60]> {
-    let mut sleep_map = HashMap::new();
-    let mut current_guard = None;
-    let mut fell_asleep_at = None;
+/// The chance of the guard being asleep on every minute of the window, going by
+/// how many of their past shifts they were asleep on it. `None` for a guard
+/// without any past shifts.
+fn predict_asleep(data: &SleepData, guard: u32) -> Option<Vec<f64>> {
+    let shifts = data.shifts.iter()
+        .filter(|shift| shift.guard == guard)
+        .count();
+    let asleep = data.sleep_map.get(&guard)?;
+    if shifts == 0 {
+        return None;
+    }
+    Some(asleep.iter()
+        .map(|&times_slept| times_slept as f64 / shifts as f64)
+        .collect())
+}
+
+/// The start of the `length` consecutive minutes with the highest average
+/// chance of the guard being asleep, and that average. Ties go to the earliest
+/// start.
+fn best_window(probabilities: &[f64], length: usize) -> Option<(usize, f64)> {
+    if length == 0 || length > probabilities.len() {
+        return None;
+    }
+    let mut best: Option<(usize, f64)> = None;
+    for (start, minutes) in probabilities.windows(length).enumerate() {
+        let average = minutes.iter().sum::<f64>() / length as f64;
+        if best.is_none_or(|(_, best_average)| average > best_average) {
+            best = Some((start, average));
+        }
+    }
+    best
+}
+
+/// Parses a roster with a line per night, like `1518-12-01 #10`.
+fn parse_roster(input: &str) -> Result<Roster, String> {
+    lazy_static! {
+        static ref RE: Regex = Regex::new(r"^\s*(\d{4}-\d{2}-\d{2})\s+#?(\d+)\s*$").unwrap();
+    }
+    input.lines()
+        .filter(|line| !line.trim().is_empty())
+        .map(|line| {
+            let captures = RE.captures(line)
+                .ok_or_else(|| format!("Can't understand the roster line '{}'", line))?;
+            let date = NaiveDate::parse_from_str(&captures[1], "%Y-%m-%d")
+                .map_err(|error| format!("Can't use '{}' as a date: {}", &captures[1], error))?;
+            let guard = captures[2].parse()
+                .map_err(|error| format!("Can't use '{}' as a guard: {}", &captures[2], error))?;
+            Ok((date, guard))
+        })
+        .collect()
+}
+
+impl Random {
+    fn new(seed: u64) -> Random {
+        // Xorshift gets stuck on a state of zero.
+        Random {state: seed ^ 0x9e37_79b9_7f4a_7c15}
+    }
+
+    fn next(&mut self) -> u64 {
+        self.state ^= self.state << 13;
+        self.state ^= self.state >> 7;
+        self.state ^= self.state << 17;
+        self.state
+    }
+
+    /// A number from `low` up to and including `high`.
+    fn between(&mut self, low: u32, high: u32) -> u32 {
+        low + (self.next() % (high - low + 1) as u64) as u32
+    }
+
+    fn shuffle<T>(&mut self, items: &mut [T]) {
+        for i in (1..items.len()).rev() {
+            let j = (self.next() % (i + 1) as u64) as usize;
+            items.swap(i, j);
+        }
+    }
+}
+
+impl Default for GeneratorConfig {
+    fn default() -> GeneratorConfig {
+        GeneratorConfig {seed: 0, guards: 20, nights: 300, naps: (0, 3), nap_length: (1, 25)}
+    }
+}
+
+/// Generates a log of guards with random IDs, starting their shift around
+/// midnight and napping during the midnight hour.
+fn generate_log(config: &GeneratorConfig) -> Result<GeneratedLog, String> {
+    if config.guards == 0 || config.guards > MAX_GENERATED_GUARDS {
+        return Err(format!("There have to be between 1 and {} guards", MAX_GENERATED_GUARDS));
+    }
+    if config.naps.0 > config.naps.1 {
+        return Err(format!("Can't take between {} and {} naps", config.naps.0, config.naps.1));
+    }
+    if config.nap_length.0 == 0 || config.nap_length.0 > config.nap_length.1 || config.nap_length.1 > 59 {
+        return Err(format!("Naps can't last between {} and {} minutes, pick lengths from 1 to 59",
+                           config.nap_length.0, config.nap_length.1));
+    }
+
+    // IDs are drawn from a range at least twice the number of guards, so
+    // picking distinct ones doesn't take long.
+    let highest_id = (2 * config.guards as u32).max(4000);
+    let mut random = Random::new(config.seed);
+    let mut guards: Vec<u32> = Vec::new();
+    let mut taken = HashSet::new();
+    while guards.len() < config.guards {
+        let guard = random.between(1, highest_id);
+        if taken.insert(guard) {
+            guards.push(guard);
+        }
+    }
+
+    let mut lines = Vec::new();
+    let mut sleep_map: SleepMap = HashMap::new();
+    let first_night = NaiveDate::from_ymd_opt(1518, 1, 1).unwrap().and_hms_opt(0, 0, 0).unwrap();
+    for night in 0..config.nights {
+        let midnight = first_night + Duration::days(night as i64);
+        let guard = guards[random.between(0, config.guards as u32 - 1) as usize];
+        let start = midnight + Duration::minutes(random.between(0, 20) as i64 - 15);
+        lines.push(format!("[{}] Guard #{} begins shift", start.format("%Y-%m-%d %H:%M"), guard));
+
+        let asleep = sleep_map.entry(guard).or_insert_with(|| vec![0; 60]);
+        let mut awake_from = start.max(midnight).minute() + 1;
+        for _ in 0..random.between(config.naps.0, config.naps.1) {
+            let falls_asleep = awake_from + random.between(0, 15);
+            let wakes_up = (falls_asleep + random.between(config.nap_length.0, config.nap_length.1)).min(59);
+            if falls_asleep >= wakes_up {
+                break;
+            }
+            for minute in falls_asleep..wakes_up {
+                asleep[minute as usize] += 1;
+            }
+            let at = |minute| (midnight + Duration::minutes(minute as i64)).format("%Y-%m-%d %H:%M");
+            lines.push(format!("[{}] falls asleep", at(falls_asleep)));
+            lines.push(format!("[{}] wakes up", at(wakes_up)));
+            awake_from = wakes_up + 1;
+        }
+    }
+    random.shuffle(&mut lines);
+
+    let (strategy_1, strategy_2) = expected_choices(&sleep_map);
+    Ok(GeneratedLog {lines, sleep_map, strategy_1, strategy_2})
+}
+
+/// Works out the answers of the first two strategies the straightforward way,
+/// by trying every guard and minute in order of the tie-break rules.
+fn expected_choices(sleep_map: &SleepMap) -> (Option<Choice>, Option<Choice>) {
+    let mut guards: Vec<_> = sleep_map.keys().cloned().collect();
+    guards.sort();
+
+    let mut strategy_1: Option<Choice> = None;
+    let mut strategy_2: Option<Choice> = None;
+    for guard in guards {
+        let asleep = &sleep_map[&guard];
+        let total = asleep.iter().sum::<u32>();
+        for (minute, &times_slept) in asleep.iter().enumerate() {
+            if times_slept == 0 {
+                continue;
+            }
+            if strategy_1.is_none_or(|best| total > best.score ||
+                                     (total == best.score && best.guard == guard &&
+                                      times_slept > asleep[best.window_offset])) {
+                strategy_1 = Some(Choice {guard, window_offset: minute, score: total});
+            }
+            if strategy_2.is_none_or(|best| times_slept > best.score) {
+                strategy_2 = Some(Choice {guard, window_offset: minute, score: times_slept});
+            }
+        }
+    }
+    (strategy_1, strategy_2)
+}
+
+fn parse_date(date: &str) -> NaiveDate {
+    NaiveDate::parse_from_str(date, "%Y-%m-%d").unwrap()
+}
+
+impl Window {
+    fn new(start: &str, minutes: &str) -> Result<Window, String> {
+        let start = NaiveTime::parse_from_str(start, "%H:%M")
+            .map_err(|error| format!("Can't use '{}' as a start time: {}", start, error))?;
+        let minutes = minutes.parse()
+            .map_err(|error| format!("Can't use '{}' as a length: {}", minutes, error))?;
+        if minutes == 0 || minutes as i64 > MINUTES_PER_DAY {
+            return Err(format!("A window has to last between 1 and {} minutes", MINUTES_PER_DAY));
+        }
+        Ok(Window {start, minutes})
+    }
+
+    /// The time of day of the `i`th minute of the window.
+    fn clock_time(&self, i: usize) -> NaiveTime {
+        self.start + Duration::minutes(i as i64)
+    }
+
+    /// The minute of the hour that the `i`th minute of the window is on.
+    fn clock_minute(&self, i: usize) -> u32 {
+        self.clock_time(i).minute()
+    }
+
+    /// Which minute of the window the timestamp falls in, if any.
+    fn minute_of(&self, timestamp: &NaiveDateTime) -> Option<usize> {
+        // Timestamps can have seconds, so round down to the start of the minute
+        // rather than toward the window start.
+        let since_start = (timestamp.time() - self.start).num_seconds().div_euclid(60)
+            .rem_euclid(MINUTES_PER_DAY);
+        if since_start < self.minutes as i64 {
+            Some(since_start as usize)
+        } else {
+            None
+        }
+    }
+}
+
+impl Default for Window {
+    fn default() -> Window {
+        Window {start: NaiveTime::from_hms_opt(0, 0, 0).unwrap(), minutes: 60}
+    }
+}
 
-    for (timestamp, event) in input.iter() {
+fn build_shifts(input: &[Entry]) -> (Vec<Shift>, Vec<Anomaly>) {
+    let mut builder = ShiftBuilder::default();
+    let mut shifts: Vec<Shift> = input.iter()
+        .filter_map(|entry| builder.push(entry.clone()))
+        .collect();
+    shifts.extend(builder.finish());
+    (shifts, builder.anomalies)
+}
+
+impl ShiftBuilder {
+    /// Processes the next entry, returning the previous shift once a new
+    /// guard begins theirs.
+    fn push(&mut self, entry: Entry) -> Option<Shift> {
+        let ((timestamp, event), _) = entry;
         match event {
-            Event::GuardStarts(guard_number) => {
-                current_guard = Some(*guard_number);
+            Event::GuardStarts(guard) => {
+                let finished = self.finish();
+                self.shift = Some(Shift {guard, date: shift_date(&timestamp), naps: Vec::new()});
+                return finished;
             },
             Event::FallsAsleep => {
-                if current_guard.is_some() {
-                    fell_asleep_at = Some(timestamp.minute());
+                if self.shift.is_none() {
+                    self.anomalies.push(Anomaly::NoGuardOnDuty(entry));
+                } else if self.fell_asleep.is_some() {
+                    self.anomalies.push(Anomaly::FallsAsleepTwice(entry));
+                } else {
+                    self.fell_asleep = Some(entry);
                 }
             },
             Event::WakesUp => {
-                match (current_guard, fell_asleep_at) {
-                    (Some(guard), Some(start)) => {
-                        let stop = timestamp.minute();
-                        let mut asleep = sleep_map.entry(guard)
-                            .or_insert([0; 60]);
-                        for i in start..stop {
-                            asleep[i as usize] += 1;
-                        }
-                    },
-                    _ => {},
-                };
+                match (self.shift.as_mut(), self.fell_asleep.take()) {
+                    (None, _) => self.anomalies.push(Anomaly::NoGuardOnDuty(entry)),
+                    (Some(_), None) => self.anomalies.push(Anomaly::WakesWithoutSleeping(entry)),
+                    (Some(shift), Some(((start, _), _))) =>
+                        shift.naps.push(Nap {start, end: timestamp}),
+                }
             },
+        }
+        None
+    }
+
+    /// Ends the current shift. A nap that is still going on is dropped, as
+    /// there's no telling when the guard woke up.
+    fn finish(&mut self) -> Option<Shift> {
+        if let Some(entry) = self.fell_asleep.take() {
+            self.anomalies.push(Anomaly::ShiftEndsAsleep(entry));
+        }
+        self.shift.take()
+    }
+}
+
+/// The night a shift is for: guards starting in the evening are on duty for
+/// the midnight hour of the next day.
+fn shift_date(start: &NaiveDateTime) -> NaiveDate {
+    if start.hour() >= 12 {
+        (*start + Duration::days(1)).date()
+    } else {
+        start.date()
+    }
+}
+
+impl fmt::Display for Event {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Event::GuardStarts(guard) => write!(f, "Guard #{} begins shift", guard),
+            Event::FallsAsleep => write!(f, "falls asleep"),
+            Event::WakesUp => write!(f, "wakes up"),
+        }
+    }
+}
+
+impl fmt::Display for Anomaly {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let ((_, source), description) = match self {
+            Anomaly::NoGuardOnDuty(entry) => (entry, "no guard is on duty"),
+            Anomaly::FallsAsleepTwice(entry) => (entry, "the guard is already asleep"),
+            Anomaly::WakesWithoutSleeping(entry) => (entry, "the guard wasn't asleep"),
+            Anomaly::ShiftEndsAsleep(entry) => (entry, "the guard never wakes up"),
+            Anomaly::OutOfOrder(entry) => (entry, "it arrived too late to be put in order"),
         };
+        write!(f, "line {}: {}: {}", source.line, source.text.trim(), description)
     }
-    sleep_map
 }
 
-fn get_input() -> Vec<(NaiveDateTime, Event)> {
-    let mut output: Vec<_> = INPUT.lines()
-        .filter_map(|line| convert_line(line))
-        .collect();
-    output.sort();
-    output
+fn get_input(format: &LogFormat) -> (Vec<Entry>, Vec<Unrecognised>) {
+    parse_log(INPUT, format)
 }
 
-fn convert_line(line: &str) -> Option<(NaiveDateTime, Event)> {
+/// Parses and sorts the records of a log, keeping the line each came from.
+/// Blank lines are skipped, any other line that isn't understood is returned
+/// with its line number.
+fn parse_log(input: &str, format: &LogFormat) -> (Vec<Entry>, Vec<Unrecognised>) {
+    let mut entries = Vec::new();
+    let mut unrecognised = Vec::new();
+    for (i, line) in input.lines().enumerate() {
+        if line.trim().is_empty() {
+            continue;
+        }
+        match convert_line(line, format) {
+            Some(record) => entries.push((record, Source {line: i + 1, text: line.to_string()})),
+            None => unrecognised.push((i + 1, line.to_string())),
+        }
+    }
+    entries.sort();
+    (entries, unrecognised)
+}
+
+impl LogFormat {
+    /// Only the format of the puzzle input.
+    fn strict() -> LogFormat {
+        LogFormat {seconds: false, iso_separator: false, ignore_case: false, comments: false}
+    }
+
+    fn lenient() -> LogFormat {
+        LogFormat {seconds: true, iso_separator: true, ignore_case: true, comments: true}
+    }
+}
+
+/// Parses a single line of the log, returning `None` if it isn't in a format
+/// that's accepted.
+fn convert_line(line: &str, format: &LogFormat) -> Option<Record> {
     lazy_static! {
-        static ref RE: Regex = Regex::new(r"\[(.*)\] (.*)").unwrap();
+        static ref RE: Regex = Regex::new(
+            r"^\s*\[(\d{4}-\d{2}-\d{2})([ T])(\d{2}:\d{2})(:\d{2})?\]\s*(.*?)\s*$").unwrap();
+    }
+    let captures = RE.captures(line)?;
+    if &captures[2] == "T" && !format.iso_separator {
+        return None;
     }
-    let captures = RE.captures(line).unwrap();
-    match (captures.get(1), captures.get(2)) {
-        (Some(timestamp), Some(message)) =>
-            Some((NaiveDateTime::parse_from_str(timestamp.as_str(), "%Y-%m-%d %H:%M").unwrap(),
-            convert_to_event(message.as_str()))),
-        _ => None,
+    if captures.get(4).is_some() && !format.seconds {
+        return None;
     }
+    let timestamp = format!("{} {}{}", &captures[1], &captures[3],
+                            captures.get(4).map_or(":00", |seconds| seconds.as_str()));
+    let timestamp = NaiveDateTime::parse_from_str(&timestamp, "%Y-%m-%d %H:%M:%S").ok()?;
+
+    Some((timestamp, convert_to_event(&captures[5], format)?))
 }
 
-fn convert_to_event(message: &str) -> Event {
+fn convert_to_event(message: &str, format: &LogFormat) -> Option<Event> {
     lazy_static! {
-        static ref RE: Regex = Regex::new(r"Guard #(\d+) begins shift").unwrap();
-    }
-    match message {
-        "falls asleep" => Event::FallsAsleep,
-        "wakes up" => Event::WakesUp,
-        other => {
-            let capture = RE.captures(other).unwrap();
-            match capture.get(1) {
-                Some(guard_number) => Event::GuardStarts(guard_number.as_str().parse().unwrap()),
-                _ => panic!(),
-            }
-        },
+        static ref COMMENT: Regex = Regex::new(r"\s*(//|#\s).*$").unwrap();
+        static ref GUARD: Regex = Regex::new(r"^Guard #(\d+) begins shift$").unwrap();
+        static ref GUARD_ANY_CASE: Regex = Regex::new(r"(?i)^guard #(\d+) begins shift$").unwrap();
+    }
+    let mut message = message;
+    if format.comments {
+        if let Some(comment) = COMMENT.find(message) {
+            message = &message[..comment.start()];
+        }
+    }
+    let message = if format.ignore_case { message.to_lowercase() } else { message.to_string() };
+    let guard = if format.ignore_case { &*GUARD_ANY_CASE } else { &*GUARD };
+
+    match message.as_str() {
+        "falls asleep" => Some(Event::FallsAsleep),
+        "wakes up" => Some(Event::WakesUp),
+        other => Some(Event::GuardStarts(guard.captures(other)?[1].parse().ok()?)),
     }
 }
 
@@ -235,37 +1350,710 @@ mod tests {
             Event::FallsAsleep
         ));
 
-        assert_eq!(convert_line(input), output);
+        assert_eq!(convert_line(input, &LogFormat::strict()), output);
     }
 
     #[test]
     fn test_convert_to_event_falls_asleep() {
         let input = "falls asleep";
-        let output = Event::FallsAsleep;
+        let output = Some(Event::FallsAsleep);
 
-        assert_eq!(convert_to_event(input), output);
+        assert_eq!(convert_to_event(input, &LogFormat::strict()), output);
     }
 
     #[test]
     fn test_convert_to_event_wakes_up() {
         let input = "wakes up";
-        let output = Event::WakesUp;
+        let output = Some(Event::WakesUp);
 
-        assert_eq!(convert_to_event(input), output);
+        assert_eq!(convert_to_event(input, &LogFormat::strict()), output);
     }
 
     #[test]
     fn test_convert_to_event_guard_starts() {
         let input = "Guard #10 begins shift";
-        let output = Event::GuardStarts(10);
+        let output = Some(Event::GuardStarts(10));
 
-        assert_eq!(convert_to_event(input), output);
+        assert_eq!(convert_to_event(input, &LogFormat::strict()), output);
     }
 
     #[test]
-    #[should_panic]
-    fn test_convert_to_event_panic() {
+    fn test_convert_to_event_unrecognised() {
         let input = "weirdstuff";
-        convert_to_event(input);
+
+        assert_eq!(convert_to_event(input, &LogFormat::strict()), None);
+        assert_eq!(convert_to_event(input, &LogFormat::lenient()), None);
+    }
+
+    const EXAMPLE: &str =
+        "[1518-11-01 00:00] Guard #10 begins shift\n\
+        [1518-11-01 00:05] falls asleep\n\
+        [1518-11-01 00:25] wakes up\n\
+        [1518-11-01 00:30] falls asleep\n\
+        [1518-11-01 00:55] wakes up\n\
+        [1518-11-01 23:58] Guard #99 begins shift\n\
+        [1518-11-02 00:40] falls asleep\n\
+        [1518-11-02 00:50] wakes up\n\
+        [1518-11-03 00:05] Guard #10 begins shift\n\
+        [1518-11-03 00:24] falls asleep\n\
+        [1518-11-03 00:29] wakes up\n\
+        [1518-11-04 00:02] Guard #99 begins shift\n\
+        [1518-11-04 00:36] falls asleep\n\
+        [1518-11-04 00:46] wakes up\n\
+        [1518-11-05 00:03] Guard #99 begins shift\n\
+        [1518-11-05 00:45] falls asleep\n\
+        [1518-11-05 00:55] wakes up";
+
+    fn entries(input: &str) -> Vec<Entry> {
+        input.lines()
+            .enumerate()
+            .filter(|(_, line)| !line.trim().is_empty())
+            .map(|(i, line)| entry(i + 1, line))
+            .collect()
+    }
+
+    fn entry(line: usize, text: &str) -> Entry {
+        let record = convert_line(text, &LogFormat::strict()).unwrap();
+        (record, Source {line, text: text.to_string()})
+    }
+
+    fn at(timestamp: &str) -> NaiveDateTime {
+        NaiveDateTime::parse_from_str(timestamp, "%Y-%m-%d %H:%M").unwrap()
+    }
+
+    fn nap(start: &str, end: &str) -> Nap {
+        Nap {start: at(start), end: at(end)}
+    }
+
+    #[test]
+    fn test_build_shifts() {
+        let (shifts, anomalies) = build_shifts(&entries(EXAMPLE));
+
+        assert_eq!(shifts.len(), 5);
+        assert_eq!(shifts[0], Shift {
+            guard: 10,
+            date: NaiveDate::from_ymd_opt(1518, 11, 1).unwrap(),
+            naps: vec![
+                nap("1518-11-01 00:05", "1518-11-01 00:25"),
+                nap("1518-11-01 00:30", "1518-11-01 00:55")],
+        });
+        assert_eq!(shifts[1], Shift {
+            guard: 99,
+            date: NaiveDate::from_ymd_opt(1518, 11, 2).unwrap(),
+            naps: vec![nap("1518-11-02 00:40", "1518-11-02 00:50")],
+        });
+        assert_eq!(anomalies, vec![]);
+    }
+
+    #[test]
+    fn test_build_shifts_anomalies() {
+        let input =
+            "[1518-11-01 00:01] falls asleep\n\
+            [1518-11-01 00:02] Guard #10 begins shift\n\
+            [1518-11-01 00:03] wakes up\n\
+            [1518-11-01 00:05] falls asleep\n\
+            [1518-11-01 00:07] falls asleep\n\
+            [1518-11-01 00:09] wakes up\n\
+            [1518-11-01 00:30] falls asleep\n\
+            [1518-11-02 00:00] Guard #99 begins shift";
+        let (shifts, anomalies) = build_shifts(&entries(input));
+
+        assert_eq!(shifts[0].naps, vec![nap("1518-11-01 00:05", "1518-11-01 00:09")]);
+        assert_eq!(shifts[1].naps, vec![]);
+        assert_eq!(anomalies, vec![
+            Anomaly::NoGuardOnDuty(entry(1, "[1518-11-01 00:01] falls asleep")),
+            Anomaly::WakesWithoutSleeping(entry(3, "[1518-11-01 00:03] wakes up")),
+            Anomaly::FallsAsleepTwice(entry(5, "[1518-11-01 00:07] falls asleep")),
+            Anomaly::ShiftEndsAsleep(entry(7, "[1518-11-01 00:30] falls asleep"))]);
+    }
+
+    #[test]
+    fn test_anomaly_display() {
+        let anomaly = Anomaly::WakesWithoutSleeping(entry(12, "[1518-11-01 00:03] wakes up"));
+
+        assert_eq!(anomaly.to_string(), "line 12: [1518-11-01 00:03] wakes up: the guard wasn't asleep");
+    }
+
+    #[test]
+    fn test_guards_sleep_map() {
+        let (shifts, _) = build_shifts(&entries(EXAMPLE));
+        let sleep_map = guards_sleep_map(&shifts, &Window::default());
+
+        assert_eq!(sleep_map[&10].iter().sum::<u32>(), 50);
+        assert_eq!(sleep_map[&10][24], 2);
+        assert_eq!(sleep_map[&99].iter().sum::<u32>(), 30);
+        assert_eq!(sleep_map[&99][45], 3);
+    }
+
+    fn example_data() -> SleepData {
+        let window = Window::default();
+        let (shifts, _) = build_shifts(&entries(EXAMPLE));
+        let sleep_map = guards_sleep_map(&shifts, &window);
+        SleepData {shifts, sleep_map, window}
+    }
+
+    #[test]
+    fn test_most_minutes_asleep() {
+        assert_eq!(MostMinutesAsleep.choose(&example_data()),
+                   vec![Choice {guard: 10, window_offset: 24, score: 50}]);
+    }
+
+    #[test]
+    fn test_answer_in_other_window() {
+        let (shifts, _) = build_shifts(&entries(EXAMPLE));
+        let window = Window::new("23:30", "90").unwrap();
+        let sleep_map = guards_sleep_map(&shifts, &window);
+        let data = SleepData {shifts, sleep_map, window};
+        let default_data = example_data();
+
+        let strategies: [(&dyn Strategy, u32); 2] = [(&MostMinutesAsleep, 240), (&MostFrequentMinute, 4455)];
+        for (strategy, answer) in strategies.iter() {
+            let choice = strategy.choose(&data)[0];
+            let default_choice = strategy.choose(&default_data)[0];
+            assert_eq!(choice.guard, default_choice.guard);
+            assert_eq!(choice.answer(&window), *answer);
+            assert_eq!(default_choice.answer(&default_data.window), *answer);
+        }
+    }
+
+    #[test]
+    fn test_window_clock_minute() {
+        let window = Window::new("23:30", "90").unwrap();
+        assert_eq!(window.clock_minute(0), 30);
+        assert_eq!(window.clock_minute(29), 59);
+        assert_eq!(window.clock_minute(30), 0);
+        assert_eq!(window.clock_minute(89), 59);
+    }
+
+    #[test]
+    fn test_most_frequent_minute() {
+        assert_eq!(MostFrequentMinute.choose(&example_data()),
+                   vec![Choice {guard: 99, window_offset: 45, score: 3}]);
+    }
+
+    #[test]
+    fn test_most_consistent_sleeper() {
+        let mut data = example_data();
+        data.shifts.push(Shift {
+            guard: 10,
+            date: NaiveDate::from_ymd_opt(1518, 11, 6).unwrap(),
+            naps: vec![],
+        });
+
+        assert_eq!(MostConsistentSleeper.choose(&data),
+                   vec![Choice {guard: 99, window_offset: 45, score: 100}]);
+    }
+
+    #[test]
+    fn test_latest_average_nap() {
+        assert_eq!(LatestAverageNap.choose(&example_data()),
+                   vec![Choice {guard: 99, window_offset: 40, score: 40}]);
+    }
+
+    #[test]
+    fn test_most_distinct_days() {
+        assert_eq!(MostDistinctDays.choose(&example_data()),
+                   vec![Choice {guard: 99, window_offset: 45, score: 3}]);
+    }
+
+    #[test]
+    fn test_strategies_without_sleep() {
+        let data = SleepData {shifts: vec![], sleep_map: HashMap::new(), window: Window::default()};
+
+        assert!(all_strategies().iter().all(|strategy| strategy.choose(&data).is_empty()));
+    }
+
+    fn tied_data() -> SleepData {
+        let mut sleep_map = HashMap::new();
+        let mut asleep_7 = vec![0; 60];
+        asleep_7[30] = 2;
+        asleep_7[10] = 2;
+        asleep_7[20] = 1;
+        let mut asleep_3 = vec![0; 60];
+        asleep_3[40] = 2;
+        asleep_3[50] = 2;
+        asleep_3[45] = 1;
+        let mut asleep_5 = vec![0; 60];
+        asleep_5[0] = 1;
+        sleep_map.insert(7, asleep_7);
+        sleep_map.insert(3, asleep_3);
+        sleep_map.insert(5, asleep_5);
+        SleepData {shifts: vec![], sleep_map, window: Window::default()}
+    }
+
+    #[test]
+    fn test_most_minutes_asleep_ties() {
+        assert_eq!(MostMinutesAsleep.choose(&tied_data()), vec![
+            Choice {guard: 3, window_offset: 40, score: 5},
+            Choice {guard: 3, window_offset: 50, score: 5},
+            Choice {guard: 7, window_offset: 10, score: 5},
+            Choice {guard: 7, window_offset: 30, score: 5}]);
+    }
+
+    #[test]
+    fn test_most_frequent_minute_ties() {
+        assert_eq!(MostFrequentMinute.choose(&tied_data()), vec![
+            Choice {guard: 3, window_offset: 40, score: 2},
+            Choice {guard: 3, window_offset: 50, score: 2},
+            Choice {guard: 7, window_offset: 10, score: 2},
+            Choice {guard: 7, window_offset: 30, score: 2}]);
+    }
+
+    #[test]
+    fn test_choice_is_stable() {
+        let choices: Vec<_> = (0..20)
+            .map(|_| MostFrequentMinute.choose(&tied_data())[0])
+            .collect();
+
+        assert!(choices.iter().all(|choice| *choice == Choice {guard: 3, window_offset: 40, score: 2}));
+    }
+
+    #[test]
+    fn test_sleepiest_minutes() {
+        assert_eq!(sleepiest_minutes(&[0, 3, 1, 3]), (vec![1, 3], 3));
+        assert_eq!(sleepiest_minutes(&[0, 0]), (vec![], 0));
+    }
+
+    #[test]
+    fn test_find_strategy() {
+        assert_eq!(find_strategy("same-minute").map(|strategy| strategy.name()), Some("same-minute"));
+        assert!(find_strategy("random").is_none());
+    }
+
+    #[test]
+    fn test_render_chart() {
+        let (shifts, _) = build_shifts(&entries(EXAMPLE));
+        let output = "\
+Date   ID   Minute
+            000000000011111111112222222222333333333344444444445555555555
+            012345678901234567890123456789012345678901234567890123456789
+11-01  #10  .....####################.....#########################.....
+11-02  #99  ........................................##########..........
+11-03  #10  ........................#####...............................
+11-04  #99  ....................................##########..............
+11-05  #99  .............................................##########.....
+";
+
+        assert_eq!(render_chart(&shifts, &Window::default(), &ChartFilter::default()), output);
+    }
+
+    #[test]
+    fn test_render_chart_filtered() {
+        let (shifts, _) = build_shifts(&entries(EXAMPLE));
+        let window = Window::new("00:40", "10").unwrap();
+        let filter = ChartFilter {
+            guard: Some(99),
+            from: None,
+            to: Some(NaiveDate::from_ymd_opt(1518, 11, 4).unwrap()),
+        };
+        let output = "\
+Date   ID   Minute
+            4444444444
+            0123456789
+11-02  #99  ##########
+11-04  #99  ######....
+";
+
+        assert_eq!(render_chart(&shifts, &window, &filter), output);
+    }
+
+    #[test]
+    fn test_render_histogram() {
+        let window = Window::new("00:58", "4").unwrap();
+        let output = "\
+Guard #10, asleep for 4 minutes
+    2  .#..
+    1  ###.
+       5500
+       8901
+";
+
+        assert_eq!(render_histogram(10, &[1, 2, 1, 0], &window), output);
+    }
+
+    #[test]
+    fn test_nap_stats() {
+        let naps = [
+            nap("1518-11-01 00:05", "1518-11-01 00:25"),
+            nap("1518-11-01 00:30", "1518-11-01 00:55"),
+            nap("1518-11-03 00:24", "1518-11-03 00:29")];
+        let naps: Vec<_> = naps.iter().collect();
+
+        assert_eq!(nap_stats(&naps), Some(NapStats {count: 3, mean: 50.0 / 3.0, median: 20.0, longest: 25}));
+        assert_eq!(nap_stats(&naps[..2]), Some(NapStats {count: 2, mean: 22.5, median: 22.5, longest: 25}));
+        assert_eq!(nap_stats(&[]), None);
+    }
+
+    #[test]
+    fn test_export_csv() {
+        let files = export_csv(&example_data());
+        let names: Vec<_> = files.iter().map(|(name, _)| *name).collect();
+        assert_eq!(names, vec!["guards.csv", "minutes.csv", "naps.csv", "summary.csv"]);
+
+        let guards = "\
+guard,shifts,naps,minutes_asleep,mean_nap,median_nap,longest_nap
+10,2,3,50,16.666666666666668,20,25
+99,3,3,30,10,10,10
+";
+        assert_eq!(files[0].1, guards);
+
+        let minutes: Vec<_> = files[1].1.lines().collect();
+        assert_eq!(minutes.len(), 3);
+        assert!(minutes[0].starts_with("guard,00:00,00:01,00:02,"));
+        assert!(minutes[0].ends_with(",00:59"));
+        assert!(minutes[2].starts_with("99,0,0,"));
+        assert_eq!(minutes[2].split(',').nth(46), Some("3"));
+
+        let naps: Vec<_> = files[2].1.lines().collect();
+        assert_eq!(naps.len(), 7);
+        assert_eq!(naps[3], "99,1518-11-02,1518-11-02 00:40,1518-11-02 00:50,10");
+
+        assert_eq!(files[3].1, "guards,shifts,naps,mean_nap,median_nap,longest_nap\n2,5,6,13.333333333333334,10,25\n");
+    }
+
+    #[test]
+    fn test_export_json() {
+        let json = export_json(&example_data());
+
+        assert!(json.starts_with("{\n  \"guards\": [\n    {\"guard\": 10, \"shifts\": 2, \"minutes_asleep\": 50, "));
+        assert!(json.contains("{\"guard\": 99, \"date\": \"1518-11-02\", \"naps\": \
+                               [{\"start\": \"1518-11-02 00:40\", \"end\": \"1518-11-02 00:50\", \"minutes\": 10}]}"));
+        assert!(json.contains("\"minutes\": {\"00:00\": 0, \"00:01\": 0, "));
+        assert!(json.contains("\"00:45\": 3, "));
+        assert!(json.ends_with("\"summary\": {\"count\": 6, \"mean\": 13.333333333333334, \"median\": 10, \"longest\": 25}\n}\n"));
+    }
+
+    #[test]
+    fn test_export_labels_clock_minutes() {
+        let (shifts, _) = build_shifts(&entries(EXAMPLE));
+        let window = Window::new("23:30", "90").unwrap();
+        let sleep_map = guards_sleep_map(&shifts, &window);
+        let data = SleepData {shifts, sleep_map, window};
+
+        let files = export_csv(&data);
+        let header = files[1].1.lines().next().unwrap();
+        assert!(header.starts_with("guard,23:30,23:31,"));
+        assert!(header.contains(",23:59,00:00,"));
+        assert!(header.ends_with(",00:59"));
+        assert_eq!(files[1].1.lines().nth(2).unwrap().split(',').nth(76), Some("3"));
+
+        assert!(export_json(&data).contains("\"minutes\": {\"23:30\": 0, "));
+    }
+
+    /// The example log, with every record moved at most two places.
+    const EXAMPLE_SHUFFLED: &str =
+        "[1518-11-01 00:05] falls asleep\n\
+        [1518-11-01 00:00] Guard #10 begins shift\n\
+        [1518-11-01 00:30] falls asleep\n\
+        [1518-11-01 00:55] wakes up\n\
+        [1518-11-01 00:25] wakes up\n\
+        [1518-11-02 00:40] falls asleep\n\
+        [1518-11-01 23:58] Guard #99 begins shift\n\
+        \n\
+        [1518-11-02 00:50] wakes up\n\
+        [1518-11-03 00:24] falls asleep\n\
+        [1518-11-03 00:29] wakes up\n\
+        [1518-11-03 00:05] Guard #10 begins shift\n\
+        [1518-11-04 00:02] Guard #99 begins shift\n\
+        [1518-11-04 00:36] falls asleep\n\
+        [1518-11-04 00:46] wakes up\n\
+        [1518-11-05 00:45] falls asleep\n\
+        [1518-11-05 00:03] Guard #99 begins shift\n\
+        [1518-11-05 00:55] wakes up";
+
+    #[test]
+    fn test_log_stream() {
+        let mut log = LogStream::new(2, Window::default());
+        let unrecognised = log.read_from(EXAMPLE_SHUFFLED.as_bytes(), &LogFormat::strict()).unwrap();
+        let (shifts, anomalies, sleep_map) = log.finish();
+
+        assert_eq!(unrecognised, vec![]);
+
+        let (expected_shifts, _) = build_shifts(&entries(EXAMPLE));
+        assert_eq!(shifts, expected_shifts);
+        assert_eq!(anomalies, vec![]);
+        assert_eq!(sleep_map, guards_sleep_map(&expected_shifts, &Window::default()));
+    }
+
+    #[test]
+    fn test_log_stream_incremental() {
+        let mut log = LogStream::new(2, Window::default());
+        let mut input = entries(EXAMPLE_SHUFFLED).into_iter();
+
+        for entry in input.by_ref().take(9) {
+            log.push(entry);
+        }
+        assert_eq!(log.shifts.len(), 1);
+        assert_eq!(log.sleep_map[&10].iter().sum::<u32>(), 45);
+        assert!(!log.sleep_map.contains_key(&99));
+
+        for entry in input {
+            log.push(entry);
+        }
+        assert_eq!(log.shifts.len(), 4);
+        assert_eq!(log.sleep_map[&99].iter().sum::<u32>(), 20);
+    }
+
+    #[test]
+    fn test_log_stream_buffer_too_small() {
+        let mut log = LogStream::new(1, Window::default());
+        log.read_from(EXAMPLE_SHUFFLED.as_bytes(), &LogFormat::strict()).unwrap();
+        let (_, anomalies, _) = log.finish();
+
+        assert_eq!(anomalies, vec![
+            Anomaly::FallsAsleepTwice(entry(3, "[1518-11-01 00:30] falls asleep")),
+            Anomaly::OutOfOrder(entry(5, "[1518-11-01 00:25] wakes up")),
+            Anomaly::OutOfOrder(entry(12, "[1518-11-03 00:05] Guard #10 begins shift"))]);
+    }
+
+    #[test]
+    fn test_log_stream_same_timestamp_late() {
+        // The guard starting at 00:05 sorts before falling asleep at 00:05,
+        // which has already been made final when it arrives.
+        let input =
+            "[1518-11-01 00:05] falls asleep\n\
+            [1518-11-01 00:10] wakes up\n\
+            [1518-11-01 00:05] Guard #10 begins shift";
+        let mut log = LogStream::new(1, Window::default());
+        log.read_from(input.as_bytes(), &LogFormat::strict()).unwrap();
+        let (_, anomalies, _) = log.finish();
+
+        assert_eq!(anomalies, vec![
+            Anomaly::NoGuardOnDuty(entry(1, "[1518-11-01 00:05] falls asleep")),
+            Anomaly::OutOfOrder(entry(3, "[1518-11-01 00:05] Guard #10 begins shift")),
+            Anomaly::NoGuardOnDuty(entry(2, "[1518-11-01 00:10] wakes up"))]);
+    }
+
+    #[test]
+    fn test_convert_line_strict() {
+        let format = LogFormat::strict();
+
+        assert_eq!(convert_line("[1518-11-01 00:05] falls asleep", &format),
+                   Some((at("1518-11-01 00:05"), Event::FallsAsleep)));
+        assert_eq!(convert_line("[1518-11-01 00:00] Guard #10 begins shift", &format),
+                   Some((at("1518-11-01 00:00"), Event::GuardStarts(10))));
+        assert_eq!(convert_line("[1518-11-01 00:05:30] falls asleep", &format), None);
+        assert_eq!(convert_line("[1518-11-01T00:05] falls asleep", &format), None);
+        assert_eq!(convert_line("[1518-11-01 00:00] guard #10 begins shift", &format), None);
+        assert_eq!(convert_line("[1518-11-01 00:05] falls asleep // again", &format), None);
+        assert_eq!(convert_line("weirdstuff", &format), None);
+        assert_eq!(convert_line("[1518-13-01 00:05] falls asleep", &format), None);
+    }
+
+    #[test]
+    fn test_convert_line_lenient() {
+        let format = LogFormat::lenient();
+
+        assert_eq!(convert_line("[1518-11-01 00:05:30] falls asleep", &format),
+                   Some((at("1518-11-01 00:05") + Duration::seconds(30), Event::FallsAsleep)));
+        assert_eq!(convert_line("[1518-11-01T00:05] Wakes Up", &format),
+                   Some((at("1518-11-01 00:05"), Event::WakesUp)));
+        assert_eq!(convert_line("[1518-11-01 00:00] guard #10 begins shift", &format),
+                   Some((at("1518-11-01 00:00"), Event::GuardStarts(10))));
+        assert_eq!(convert_line("[1518-11-01 00:00] Guard #10 begins shift # the new one", &format),
+                   Some((at("1518-11-01 00:00"), Event::GuardStarts(10))));
+        assert_eq!(convert_line("[1518-11-01 00:05] falls asleep // again", &format),
+                   Some((at("1518-11-01 00:05"), Event::FallsAsleep)));
+        assert_eq!(convert_line("[1518-11-01 00:05] dances", &format), None);
+    }
+
+    #[test]
+    fn test_parse_log() {
+        let input =
+            "[1518-11-01 00:05] falls asleep\n\
+            \n\
+            [1518-11-01 00:00] Guard #10 begins shift\n\
+            weirdstuff\n\
+            [1518-11-01 00:25] wakes up";
+        let (entries, unrecognised) = parse_log(input, &LogFormat::strict());
+
+        assert_eq!(entries, vec![
+            entry(3, "[1518-11-01 00:00] Guard #10 begins shift"),
+            entry(1, "[1518-11-01 00:05] falls asleep"),
+            entry(5, "[1518-11-01 00:25] wakes up")]);
+        assert_eq!(unrecognised, vec![(4, "weirdstuff".to_string())]);
+    }
+
+    #[test]
+    fn test_predict_asleep() {
+        let data = example_data();
+        let probabilities = predict_asleep(&data, 99).unwrap();
+
+        assert_eq!(probabilities.len(), 60);
+        assert_eq!(probabilities[35], 0.0);
+        assert_eq!(probabilities[36], 1.0 / 3.0);
+        assert_eq!(probabilities[45], 1.0);
+        assert_eq!(predict_asleep(&data, 42), None);
+    }
+
+    #[test]
+    fn test_best_window() {
+        let probabilities = [0.0, 0.5, 0.25, 1.0, 0.0, 0.75, 0.5];
+
+        assert_eq!(best_window(&probabilities, 1), Some((3, 1.0)));
+        assert_eq!(best_window(&probabilities, 2), Some((2, 0.625)));
+        assert_eq!(best_window(&probabilities, 3), Some((1, 0.5833333333333334)));
+        assert_eq!(best_window(&probabilities, 7), Some((0, 3.0 / 7.0)));
+        assert_eq!(best_window(&probabilities, 8), None);
+        assert_eq!(best_window(&probabilities, 0), None);
+    }
+
+    #[test]
+    fn test_best_window_ties() {
+        assert_eq!(best_window(&[0.5, 0.5, 0.0, 0.5, 0.5], 2), Some((0, 0.5)));
+    }
+
+    #[test]
+    fn test_best_window_predicted() {
+        let data = example_data();
+        let probabilities = predict_asleep(&data, 10).unwrap();
+
+        assert_eq!(best_window(&probabilities, 5), Some((20, 0.6)));
+    }
+
+    #[test]
+    fn test_parse_roster() {
+        let input = "1518-12-01 #10\n\n1518-12-02 99\n";
+
+        assert_eq!(parse_roster(input), Ok(vec![
+            (NaiveDate::from_ymd_opt(1518, 12, 1).unwrap(), 10),
+            (NaiveDate::from_ymd_opt(1518, 12, 2).unwrap(), 99)]));
+        assert!(parse_roster("1518-12-01 Bob").is_err());
+        assert!(parse_roster("1518-02-30 #10").is_err());
+    }
+
+    #[test]
+    fn test_generate_log_is_seeded() {
+        let config = GeneratorConfig {seed: 7, guards: 5, nights: 20, ..GeneratorConfig::default()};
+
+        assert_eq!(generate_log(&config).unwrap().lines, generate_log(&config).unwrap().lines);
+        assert_ne!(generate_log(&config).unwrap().lines,
+                   generate_log(&GeneratorConfig {seed: 8, ..config}).unwrap().lines);
+    }
+
+    #[test]
+    fn test_generate_log_many_guards() {
+        let config = GeneratorConfig {guards: 5000, nights: 5, ..GeneratorConfig::default()};
+        assert_eq!(generate_log(&config).unwrap().lines.iter()
+                       .filter(|line| line.ends_with("begins shift"))
+                       .count(), 5);
+    }
+
+    #[test]
+    fn test_generate_log_invalid_config() {
+        let config = GeneratorConfig::default();
+        assert!(generate_log(&GeneratorConfig {guards: 0, ..config}).is_err());
+        assert!(generate_log(&GeneratorConfig {guards: MAX_GENERATED_GUARDS + 1, ..config}).is_err());
+        assert!(generate_log(&GeneratorConfig {naps: (3, 1), ..config}).is_err());
+        assert!(generate_log(&GeneratorConfig {nap_length: (0, 5), ..config}).is_err());
+        assert!(generate_log(&GeneratorConfig {nap_length: (10, 5), ..config}).is_err());
+        assert!(generate_log(&GeneratorConfig {nap_length: (10, 60), ..config}).is_err());
+    }
+
+    #[test]
+    fn test_generated_logs_end_to_end() {
+        let configs = [
+            GeneratorConfig::default(),
+            GeneratorConfig {guards: 1, nights: 10, ..GeneratorConfig::default()},
+            GeneratorConfig {guards: 3, nights: 50, naps: (1, 1), nap_length: (5, 5), ..GeneratorConfig::default()},
+            GeneratorConfig {guards: 30, nights: 40, naps: (0, 6), nap_length: (1, 4), ..GeneratorConfig::default()},
+            GeneratorConfig {guards: 4, nights: 12, naps: (0, 0), ..GeneratorConfig::default()},
+        ];
+        for (i, config) in configs.iter().enumerate() {
+            for seed in 0..10 {
+                let config = GeneratorConfig {seed: seed * 31 + i as u64, ..*config};
+                let log = generate_log(&config).unwrap();
+
+                let (entries, unrecognised) = parse_log(&log.lines.join("\n"), &LogFormat::strict());
+                assert_eq!(unrecognised, vec![]);
+                let (shifts, anomalies) = build_shifts(&entries);
+                assert_eq!(anomalies, vec![]);
+                assert_eq!(shifts.len(), config.nights);
+
+                let window = Window::default();
+                let sleep_map = guards_sleep_map(&shifts, &window);
+                assert_eq!(sleep_map, log.sleep_map, "{:?}", config);
+
+                let data = SleepData {shifts, sleep_map, window};
+                assert_eq!(MostMinutesAsleep.choose(&data).first(), log.strategy_1.as_ref(), "{:?}", config);
+                assert_eq!(MostFrequentMinute.choose(&data).first(), log.strategy_2.as_ref(), "{:?}", config);
+            }
+        }
+    }
+
+    fn naps_sleep_map(naps: Vec<Nap>, window: &Window) -> Vec<u32> {
+        let date = NaiveDate::from_ymd_opt(1518, 11, 1).unwrap();
+        let shifts = vec![Shift {guard: 10, date, naps}];
+        guards_sleep_map(&shifts, window).remove(&10).unwrap()
+    }
+
+    #[test]
+    fn test_guards_sleep_map_nap_past_midnight() {
+        let naps = vec![nap("1518-10-31 23:58", "1518-11-01 00:10")];
+        let asleep = naps_sleep_map(naps, &Window::default());
+
+        assert_eq!(asleep.iter().sum::<u32>(), 10);
+        assert!(asleep[..10].iter().all(|&times_slept| times_slept == 1));
+    }
+
+    #[test]
+    fn test_guards_sleep_map_nap_past_the_hour() {
+        let naps = vec![nap("1518-11-01 00:50", "1518-11-01 01:10")];
+        let asleep = naps_sleep_map(naps, &Window::default());
+
+        assert_eq!(asleep.iter().sum::<u32>(), 10);
+        assert!(asleep[50..].iter().all(|&times_slept| times_slept == 1));
+    }
+
+    #[test]
+    fn test_guards_sleep_map_nap_spanning_days() {
+        let naps = vec![nap("1518-11-01 00:30", "1518-11-02 00:20")];
+        let asleep = naps_sleep_map(naps, &Window::default());
+
+        assert_eq!(asleep.iter().sum::<u32>(), 50);
+        assert!(asleep[20..30].iter().all(|&times_slept| times_slept == 0));
+    }
+
+    #[test]
+    fn test_guards_sleep_map_window() {
+        let window = Window::new("23:30", "90").unwrap();
+        let naps = vec![nap("1518-10-31 23:58", "1518-11-01 00:10")];
+        let asleep = naps_sleep_map(naps, &window);
+
+        assert_eq!(asleep.len(), 90);
+        assert_eq!(asleep.iter().sum::<u32>(), 12);
+        assert_eq!(asleep[27], 0);
+        assert_eq!(asleep[28], 1);
+        assert_eq!(asleep[39], 1);
+        assert_eq!(asleep[40], 0);
+    }
+
+    #[test]
+    fn test_window_minute_of() {
+        let window = Window::new("23:00", "120").unwrap();
+
+        assert_eq!(window.minute_of(&at("1518-11-01 22:59")), None);
+        assert_eq!(window.minute_of(&at("1518-11-01 23:00")), Some(0));
+        assert_eq!(window.minute_of(&at("1518-11-02 00:30")), Some(90));
+        assert_eq!(window.minute_of(&at("1518-11-02 01:00")), None);
+    }
+
+    #[test]
+    fn test_window_minute_of_seconds() {
+        let window = Window::new("23:30", "90").unwrap();
+
+        assert_eq!(window.minute_of(&(at("1518-11-01 23:29") + Duration::seconds(30))), None);
+        assert_eq!(window.minute_of(&(at("1518-11-01 23:30") + Duration::seconds(30))), Some(0));
+        assert_eq!(window.minute_of(&(at("1518-11-02 00:59") + Duration::seconds(59))), Some(89));
+
+        let nap = Nap {start: at("1518-11-01 23:29") + Duration::seconds(30), end: at("1518-11-01 23:31")};
+        assert_eq!(nap.minutes_in(&window), vec![0]);
+    }
+
+    #[test]
+    fn test_window_new_invalid() {
+        assert!(Window::new("25:00", "60").is_err());
+        assert!(Window::new("00:00", "0").is_err());
+        assert!(Window::new("00:00", "1441").is_err());
     }
 }
@@ -64,45 +64,563 @@
 /// What is the length of the shortest polymer you can produce by removing all
 /// units of exactly one type and fully reacting the result?
 
+use std::collections::{BTreeMap, HashMap};
+use std::fs::File;
+use std::io::{self, Read, Write};
+use std::thread;
+use std::time::Instant;
+
 const INPUT: &str = include_str!("../input/day_05.txt");
+/// Every option, so an optional argument isn't mistaken for one.
+const OPTIONS: [&str; 8] = ["bench", "strict", "sanitise", "rules", "remove", "stream", "output", "trace"];
+/// How many invalid bytes of a streamed polymer are reported by offset.
+const REPORTED_OFFSETS: usize = 10;
+/// How many bytes a streamed polymer is read in at a time.
+const CHUNK_SIZE: usize = 64 * 1024;
+/// Polymers longer than this are traced as a summary rather than step by step.
+const TRACE_LIMIT: usize = 80;
+
+/// What happens when two adjacent units react.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Reaction {
+    /// Both units are destroyed.
+    Annihilate,
+    /// Both units are replaced by a single new unit.
+    Produce(char),
+}
+
+/// Decides which pairs of adjacent units react, and what they react into.
+trait ReactionRules {
+    fn react(&self, left: char, right: char) -> Option<Reaction>;
+}
+
+/// The puzzle's rules: units of the same type and opposite polarity destroy
+/// each other.
+struct CasePolarity;
+
+/// Rules given as an explicit table of reacting pairs. Pairs react in either
+/// order, so `x` annihilating `Y` also means `Y` annihilates `x`.
+#[derive(Debug, Default)]
+struct PairTable {
+    reactions: HashMap<(char, char), Reaction>,
+}
+
+/// What to do with characters in a polymer that aren't units.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Validation {
+    /// Reject the polymer.
+    Strict,
+    /// Leave them out of the polymer.
+    Sanitise,
+}
+
+/// A character that isn't a unit, at its position in the scanned text.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct InvalidUnit {
+    position: usize,
+    character: char,
+}
+
+/// A single reaction: the pair of units at `position` reacting.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Step {
+    position: usize,
+    pair: (char, char),
+    reaction: Reaction,
+}
+
+pub fn run(options: &[String]) {
+    let mut validation = Validation::Strict;
+    let mut bench_requested = false;
+    let mut rules = None;
+    let mut remove = None;
+    let mut trace = None;
+    let mut stream = None;
+    let mut output = None;
+    let mut options = options.iter().peekable();
+    while let Some(option) = options.next() {
+        match option.as_str() {
+            "bench" => bench_requested = true,
+            "strict" => validation = Validation::Strict,
+            "sanitise" => validation = Validation::Sanitise,
+            "rules" => rules = Some(options.next().expect("rules needs a table of pairs").clone()),
+            "remove" => {
+                let k: usize = options.next().expect("remove needs a number of unit types").parse().unwrap();
+                remove = Some(k);
+            },
+            "stream" => stream = Some(options.next().expect("stream needs a file, or - for stdin").clone()),
+            "output" => output = Some(options.next().expect("output needs a file, or - for stdout").clone()),
+            "trace" => {
+                // The polymer to trace is optional, so don't take the next
+                // option as one.
+                let polymer = options.next_if(|next| !OPTIONS.contains(&next.as_str()));
+                trace = Some(polymer.cloned());
+            },
+            other => println!("Unknown option for day_05: {}", other),
+        }
+    }
+
+    if bench_requested {
+        bench();
+        return;
+    }
+
+    if let Some(spec) = rules {
+        let rules = PairTable::parse(&spec).unwrap_or_else(|error| panic!("{}", error));
+        let resulting_polymer = trigger_all_with(&get_input(validation), &rules);
+        println!("Length of the polymer triggered with rules {}: {}", spec, resulting_polymer.len());
+        return;
+    }
+
+    if let Some(k) = remove {
+        let polymer: Vec<u8> = get_input(validation).iter().map(|&c| c as u8).collect();
+        let (removed, length) = best_removal(&polymer, k);
+        let removed: Vec<String> = removed.iter()
+            .map(|unit| format!("{}/{}", unit, unit.to_ascii_lowercase()))
+            .collect();
+        println!("The shortest polymer produced by removing up to {} types is: {} (removing {})",
+                 k, length, removed.join(", "));
+        return;
+    }
+
+    if let Some(text) = trace {
+        let polymer = match text.as_deref() {
+            None | Some("input") => get_input(validation),
+            Some(text) => match checked_polymer(text, validation) {
+                Ok(polymer) => polymer,
+                Err(error) => {
+                    println!("{}", error);
+                    return;
+                },
+            },
+        };
+        print!("{}", render_trace(&polymer, &CasePolarity));
+        return;
+    }
+
+    if let Some(path) = stream {
+        let mut writer: Option<Box<dyn Write>> = match output.as_deref() {
+            None => None,
+            Some("-") => Some(Box::new(io::stdout())),
+            Some(path) => Some(Box::new(File::create(path).unwrap())),
+        };
+        let result = if path == "-" {
+            reduce_stream(io::stdin(), validation, writer.as_mut())
+        } else {
+            reduce_stream(File::open(path).unwrap(), validation, writer.as_mut())
+        };
+        match result {
+            Ok((length, dropped)) => {
+                if dropped > 0 {
+                    eprintln!("Dropped {} invalid bytes", dropped);
+                }
+                eprintln!("Length of the fully triggered polymer: {}", length);
+            },
+            Err(error) => eprintln!("Can't reduce the polymer: {}", error),
+        }
+        return;
+    }
 
-pub fn run() {
-    let polymer = get_input();
+    let polymer = get_input(validation);
     println!("Length of the starting polymer: {}", polymer.len());
 
-    let resulting_polymer = trigger_all(&polymer);
+    let bytes: Vec<u8> = polymer.iter().map(|&c| c as u8).collect();
+    let resulting_polymer = reduce(&bytes);
     println!("Length of the fully triggered polymer: {}", resulting_polymer.len());
 
-    let a = 'A' as u8;
-    let mut polymer_lengths = Vec::new();
-    for unit in a..a+26 {
-        let polymer_without_unit = polymer.iter()
-            .filter(|&c| c.to_ascii_uppercase() != (unit as char))
-            .map(|c| *c)
-            .collect();
-        polymer_lengths.push(trigger_all(&polymer_without_unit).len());
+    let polymer_lengths = removal_lengths(&resulting_polymer);
+    for &(unit, length) in polymer_lengths.iter() {
+        println!("Without {}/{}: {}", unit, unit.to_ascii_lowercase(), length);
     }
 
     let shortest_length = polymer_lengths.iter()
+        .map(|&(_, length)| length)
         .min()
         .unwrap();
     println!("The shortest polymer produced by removing one type is: {}", shortest_length);
 }
 
+/// The length of the fully reacted polymer after removing each unit type, from
+/// A to Z. Removing a type and reacting gives the same result whether or not
+/// the polymer was reacted first, so this starts from the much shorter reduced
+/// polymer. Each type is tried on its own thread.
+fn removal_lengths(reduced: &[u8]) -> Vec<(char, usize)> {
+    thread::scope(|scope| {
+        let handles: Vec<_> = (b'A'..=b'Z')
+            .map(|unit| scope.spawn(move || {
+                let without_unit: Vec<u8> = reduced.iter()
+                    .cloned()
+                    .filter(|c| c.to_ascii_uppercase() != unit)
+                    .collect();
+                (unit as char, reduce(&without_unit).len())
+            }))
+            .collect();
+        handles.into_iter()
+            .map(|handle| handle.join().unwrap())
+            .collect()
+    })
+}
+
+impl ReactionRules for CasePolarity {
+    fn react(&self, left: char, right: char) -> Option<Reaction> {
+        if reacting(left, right) {
+            Some(Reaction::Annihilate)
+        } else {
+            None
+        }
+    }
+}
+
+impl PairTable {
+    fn annihilate(mut self, a: char, b: char) -> PairTable {
+        self.insert(a, b, Reaction::Annihilate);
+        self
+    }
+
+    fn produce(mut self, a: char, b: char, unit: char) -> PairTable {
+        self.insert(a, b, Reaction::Produce(unit));
+        self
+    }
+
+    fn insert(&mut self, a: char, b: char, reaction: Reaction) {
+        self.reactions.insert((a, b), reaction);
+        self.reactions.insert((b, a), reaction);
+    }
+
+    /// Parses a comma separated table, where `xY` means `x` and `Y` annihilate
+    /// and `ab>c` means `a` and `b` react into `c`.
+    fn parse(spec: &str) -> Result<PairTable, String> {
+        let mut table = PairTable::default();
+        for entry in spec.split(',') {
+            let units: Vec<char> = entry.chars().collect();
+            table = match *units.as_slice() {
+                [a, b] => table.annihilate(a, b),
+                [a, b, '>', unit] => table.produce(a, b, unit),
+                _ => return Err(format!("Invalid reaction: {:?}", entry)),
+            };
+        }
+        Ok(table)
+    }
+}
+
+impl ReactionRules for PairTable {
+    fn react(&self, left: char, right: char) -> Option<Reaction> {
+        self.reactions.get(&(left, right)).cloned()
+    }
+}
+
+/// Finds which set of up to `k` unit types to remove to get the shortest fully
+/// reacted polymer, returning the types (upper case, in order) and the length.
+/// Ties go to the fewest types, then to the types earliest in the alphabet.
+fn best_removal(polymer: &[u8], k: usize) -> (Vec<char>, usize) {
+    let reduced = reduce(polymer);
+    let mut types: Vec<u8> = reduced.iter().map(|c| c.to_ascii_uppercase()).collect();
+    types.sort();
+    types.dedup();
+
+    let mut best = (Vec::new(), reduced.len());
+    search_removals(&reduced, &types, &mut Vec::new(), k, &mut best);
+    let (removed, length) = best;
+    (removed.into_iter().map(|unit| unit as char).collect(), length)
+}
+
+/// Tries removing each of `types` from an already reacted polymer, and then
+/// further types after it, skipping branches that can't beat `best`. Sets of
+/// the same size are tried in alphabetical order, so a later set only wins a
+/// tie if it has fewer types.
+fn search_removals(polymer: &[u8], types: &[u8], removed: &mut Vec<u8>, left: usize,
+                   best: &mut (Vec<u8>, usize)) {
+    if left == 0 {
+        return;
+    }
+    // Anything found below here removes at least one more type.
+    let bound = removal_lower_bound(polymer, left);
+    if (bound, removed.len() + 1) >= (best.1, best.0.len()) {
+        return;
+    }
+    for (i, &unit) in types.iter().enumerate() {
+        if !polymer.iter().any(|c| c.to_ascii_uppercase() == unit) {
+            continue;
+        }
+        let without_unit: Vec<u8> = polymer.iter()
+            .cloned()
+            .filter(|c| c.to_ascii_uppercase() != unit)
+            .collect();
+        let reacted = reduce(&without_unit);
+        removed.push(unit);
+        if (reacted.len(), removed.len(), &*removed) < (best.1, best.0.len(), &best.0) {
+            *best = (removed.clone(), reacted.len());
+        }
+        search_removals(&reacted, &types[i+1..], removed, left - 1, best);
+        removed.pop();
+    }
+}
+
+/// The shortest a polymer could get by removing up to `left` more types.
+/// Reacting always takes one unit of each polarity of a type, so the
+/// difference between how many of each there are can never go away, unless
+/// the type is removed.
+fn removal_lower_bound(polymer: &[u8], left: usize) -> usize {
+    let mut balance: HashMap<u8, i64> = HashMap::new();
+    for &unit in polymer {
+        let polarity = if unit.is_ascii_uppercase() { 1 } else { -1 };
+        *balance.entry(unit.to_ascii_uppercase()).or_insert(0) += polarity;
+    }
+    let mut imbalances: Vec<usize> = balance.values().map(|b| b.unsigned_abs() as usize).collect();
+    imbalances.sort_by(|a, b| b.cmp(a));
+    imbalances.iter().skip(left).sum()
+}
+
 fn trigger_all(input_polymer: &Vec<char>) -> Vec<char> {
-    let mut polymer = input_polymer.clone();
+    trigger_all_with(input_polymer, &CasePolarity)
+}
+
+fn trigger_all_with<R: ReactionRules>(input_polymer: &[char], rules: &R) -> Vec<char> {
+    react_all(input_polymer, rules, None)
+}
+
+/// Fully reacts a polymer, also returning every reaction in the order they
+/// happened.
+fn trace_with<R: ReactionRules>(input_polymer: &[char], rules: &R) -> (Vec<char>, Vec<Step>) {
+    let mut steps = Vec::new();
+    let polymer = react_all(input_polymer, rules, Some(&mut steps));
+    (polymer, steps)
+}
+
+/// Fully reacts a polymer, recording the reactions in `steps` if given.
+fn react_all<R: ReactionRules>(input_polymer: &[char], rules: &R,
+                               mut steps: Option<&mut Vec<Step>>) -> Vec<char> {
+    let mut polymer = input_polymer.to_vec();
     let mut i = 0;
     while i+1 < polymer.len() {
-        if reacting(polymer[i], polymer[i+1]) {
-            polymer.drain(i..i+2);
-            i = i.saturating_sub(1);
-            continue;
+        let reaction = rules.react(polymer[i], polymer[i+1]);
+        if let (Some(steps), Some(reaction)) = (steps.as_mut(), reaction) {
+            steps.push(Step {position: i, pair: (polymer[i], polymer[i+1]), reaction});
+        }
+        match reaction {
+            Some(Reaction::Annihilate) => {
+                polymer.drain(i..i+2);
+                i = i.saturating_sub(1);
+                continue;
+            },
+            Some(Reaction::Produce(unit)) => {
+                // The new unit may react with either of its neighbours.
+                polymer[i] = unit;
+                polymer.remove(i+1);
+                i = i.saturating_sub(1);
+                continue;
+            },
+            None => {},
         }
         i += 1
     }
     polymer
 }
 
+/// Describes how a polymer reacts. Short polymers get every intermediate
+/// polymer, like the walkthrough above, and long ones a count of reactions
+/// for each unit type, ignoring polarity.
+fn render_trace<R: ReactionRules>(input_polymer: &[char], rules: &R) -> String {
+    let (resulting_polymer, steps) = trace_with(input_polymer, rules);
+    let mut output = String::new();
+    if input_polymer.len() <= TRACE_LIMIT {
+        let width = input_polymer.len() + 2;
+        let mut polymer = input_polymer.to_vec();
+        for step in steps.iter() {
+            let pair: String = [step.pair.0, step.pair.1].iter().collect();
+            let text: String = polymer.iter().collect();
+            output += &match step.reaction {
+                Reaction::Annihilate =>
+                    format!("{:width$}'{}' at {} is removed.\n", text, pair, step.position, width = width),
+                Reaction::Produce(unit) =>
+                    format!("{:width$}'{}' at {} becomes '{}'.\n", text, pair, step.position, unit, width = width),
+            };
+            match step.reaction {
+                Reaction::Annihilate => {
+                    polymer.drain(step.position..step.position+2);
+                },
+                Reaction::Produce(unit) => {
+                    polymer[step.position] = unit;
+                    polymer.remove(step.position+1);
+                },
+            }
+        }
+        let text: String = polymer.iter().collect();
+        output += &format!("{:width$}No further actions can be taken.\n", text, width = width);
+    } else {
+        let mut counts = BTreeMap::new();
+        for step in steps.iter() {
+            *counts.entry(step.pair.0.to_ascii_uppercase()).or_insert(0) += 1;
+        }
+        for (unit, count) in counts {
+            output += &format!("{}/{}: {} reactions\n", unit, unit.to_ascii_lowercase(), count);
+        }
+    }
+    output += &format!("{} reactions left {} of {} units.\n",
+                       steps.len(), resulting_polymer.len(), input_polymer.len());
+    output
+}
+
+/// Fully reacts a polymer in a single pass. Units are pushed onto a stack, and
+/// a unit that reacts with the top of the stack destroys it instead, so each
+/// unit is only looked at once.
+fn reduce(polymer: &[u8]) -> Vec<u8> {
+    let mut stack: Vec<u8> = Vec::with_capacity(polymer.len());
+    for &unit in polymer {
+        push_unit(&mut stack, unit);
+    }
+    stack
+}
+
+fn push_unit(stack: &mut Vec<u8>, unit: u8) {
+    match stack.last() {
+        Some(&top) if reacting_bytes(top, unit) => {
+            stack.pop();
+        },
+        _ => stack.push(unit),
+    }
+}
+
+/// Fully reacts a polymer read a chunk at a time, so only what's left of it is
+/// ever kept in memory. Whitespace around the polymer, such as a trailing
+/// newline, is skipped. Any other byte that isn't an ASCII letter fails a
+/// strict reduction with its byte offset, or is dropped when sanitising.
+/// Returns the length of the fully reacted polymer, which is also written to
+/// `output` if given, and how many bytes were dropped.
+fn reduce_stream<R: Read, W: Write>(mut input: R, validation: Validation, output: Option<W>)
+                                    -> io::Result<(usize, usize)> {
+    let mut stack = Vec::new();
+    let mut chunk = vec![0; CHUNK_SIZE];
+    let mut offset = 0;
+    let mut started = false;
+    // Whitespace is only invalid once a unit follows it, so runs of it are
+    // held back as (offset, length).
+    let mut whitespace: Option<(usize, usize)> = None;
+    let mut invalid_offsets = Vec::new();
+    let mut invalid = 0;
+    loop {
+        let read = match input.read(&mut chunk) {
+            Ok(0) => break,
+            Ok(read) => read,
+            Err(ref error) if error.kind() == io::ErrorKind::Interrupted => continue,
+            Err(error) => return Err(error),
+        };
+        for &byte in chunk[..read].iter() {
+            if byte.is_ascii_whitespace() {
+                if started {
+                    let (start, length) = whitespace.unwrap_or((offset, 0));
+                    whitespace = Some((start, length + 1));
+                }
+            } else {
+                started = true;
+                let mut bad = Vec::new();
+                if let Some((start, length)) = whitespace.take() {
+                    bad.extend(start..start+length);
+                }
+                if byte.is_ascii_alphabetic() {
+                    push_unit(&mut stack, byte);
+                } else {
+                    bad.push(offset);
+                }
+                invalid += bad.len();
+                let room = REPORTED_OFFSETS.saturating_sub(invalid_offsets.len());
+                invalid_offsets.extend(bad.into_iter().take(room));
+            }
+            offset += 1;
+        }
+    }
+
+    if validation == Validation::Strict && invalid > 0 {
+        let offsets: Vec<String> = invalid_offsets.iter().map(|offset| offset.to_string()).collect();
+        let more = if invalid > invalid_offsets.len() {
+            format!(" and {} more", invalid - invalid_offsets.len())
+        } else {
+            String::new()
+        };
+        return Err(io::Error::new(io::ErrorKind::InvalidData,
+                                  format!("invalid units at byte offsets {}{}", offsets.join(", "), more)));
+    }
+
+    if let Some(mut output) = output {
+        output.write_all(&stack)?;
+        output.write_all(b"\n")?;
+        output.flush()?;
+    }
+    Ok((stack.len(), invalid))
+}
+
+/// Fully reacts a polymer by splitting it into one chunk per core, reducing
+/// the chunks at the same time and then merging the leftovers in order. This
+/// works because reacting is associative: only the units left over at the end
+/// of one chunk can react with the units left over at the start of the next.
+fn parallel_reduce(polymer: &[u8]) -> Vec<u8> {
+    let cores = thread::available_parallelism().map_or(1, |n| n.get());
+    reduce_in_chunks(polymer, cores)
+}
+
+fn reduce_in_chunks(polymer: &[u8], chunks: usize) -> Vec<u8> {
+    let chunk_size = polymer.len().div_ceil(chunks.max(1)).max(1);
+    let reduced: Vec<Vec<u8>> = thread::scope(|scope| {
+        let handles: Vec<_> = polymer.chunks(chunk_size)
+            .map(|chunk| scope.spawn(move || reduce(chunk)))
+            .collect();
+        handles.into_iter()
+            .map(|handle| handle.join().unwrap())
+            .collect()
+    });
+    reduced.into_iter().fold(Vec::new(), merge)
+}
+
+/// Reacts two already reduced polymers joined together. Reactions can only
+/// happen where they meet, so once a unit of `right` survives the rest of it
+/// does too.
+fn merge(mut left: Vec<u8>, right: Vec<u8>) -> Vec<u8> {
+    let mut reacted = 0;
+    while reacted < right.len() {
+        match left.last() {
+            Some(&top) if reacting_bytes(top, right[reacted]) => {
+                left.pop();
+                reacted += 1;
+            },
+            _ => break,
+        }
+    }
+    left.extend_from_slice(&right[reacted..]);
+    left
+}
+
+/// Units of the same type and opposite polarity differ only in the ASCII case
+/// bit.
+fn reacting_bytes(a: u8, b: u8) -> bool {
+    a ^ b == 0x20 && a.is_ascii_alphabetic()
+}
+
+/// Times both reducers on the real input.
+fn bench() {
+    const RUNS: u32 = 10;
+    let polymer = get_input(Validation::Strict);
+    let bytes: &[u8] = &polymer.iter().map(|&c| c as u8).collect::<Vec<_>>();
+
+    let start = Instant::now();
+    let mut length = 0;
+    for _ in 0..RUNS {
+        length = trigger_all(&polymer).len();
+    }
+    println!("trigger_all: {} units, {:?} per run", length, start.elapsed() / RUNS);
+
+    let start = Instant::now();
+    for _ in 0..RUNS {
+        length = reduce(bytes).len();
+    }
+    println!("reduce: {} units, {:?} per run", length, start.elapsed() / RUNS);
+
+    let start = Instant::now();
+    for _ in 0..RUNS {
+        length = parallel_reduce(bytes).len();
+    }
+    println!("parallel_reduce: {} units, {:?} per run", length, start.elapsed() / RUNS);
+}
+
 fn reacting(a: char, b: char) -> bool {
     let a_uppercase = a.to_ascii_uppercase();
     if a_uppercase == b && a_uppercase != a {
@@ -117,11 +635,55 @@ fn reacting(a: char, b: char) -> bool {
     false
 }
 
-fn get_input() -> Vec<char> {
-    INPUT.trim().chars()
+/// Finds every character that isn't an ASCII letter, ignoring whitespace
+/// around the polymer.
+fn validate_polymer(text: &str) -> Vec<InvalidUnit> {
+    let leading = text.chars().take_while(|c| c.is_whitespace()).count();
+    text.trim().chars()
+        .enumerate()
+        .filter(|&(_, character)| !character.is_ascii_alphabetic())
+        .map(|(i, character)| InvalidUnit {position: leading + i, character})
         .collect()
 }
 
+/// Reads a polymer from its scanned text. A strict parse fails with the
+/// invalid units, and a sanitising parse drops them, returning the polymer
+/// with whatever was dropped.
+fn parse_polymer(text: &str, validation: Validation) -> Result<(Vec<char>, Vec<InvalidUnit>), Vec<InvalidUnit>> {
+    let invalid = validate_polymer(text);
+    if validation == Validation::Strict && !invalid.is_empty() {
+        return Err(invalid);
+    }
+    let polymer = text.trim().chars()
+        .filter(|c| c.is_ascii_alphabetic())
+        .collect();
+    Ok((polymer, invalid))
+}
+
+fn describe_invalid_units(invalid: &[InvalidUnit]) -> String {
+    let units: Vec<String> = invalid.iter()
+        .map(|unit| format!("{:?} at {}", unit.character, unit.position))
+        .collect();
+    units.join(", ")
+}
+
+/// Parses a polymer, mentioning any units that were dropped.
+fn checked_polymer(text: &str, validation: Validation) -> Result<Vec<char>, String> {
+    match parse_polymer(text, validation) {
+        Ok((polymer, dropped)) => {
+            if !dropped.is_empty() {
+                eprintln!("Dropped invalid units: {}", describe_invalid_units(&dropped));
+            }
+            Ok(polymer)
+        },
+        Err(invalid) => Err(format!("Invalid units in the polymer: {}", describe_invalid_units(&invalid))),
+    }
+}
+
+fn get_input(validation: Validation) -> Vec<char> {
+    checked_polymer(INPUT, validation).unwrap_or_else(|error| panic!("{}", error))
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -140,6 +702,354 @@ mod tests {
         assert_eq!(trigger_all(&input), output);
     }
 
+    #[test]
+    fn test_trigger_all_with_case_polarity() {
+        let input: Vec<_> = "dabAcCaCBAcCcaDA".chars().collect();
+        assert_eq!(trigger_all_with(&input, &CasePolarity), trigger_all(&input));
+    }
+
+    #[test]
+    fn test_trigger_all_with_pair_table() {
+        let rules = PairTable::default()
+            .annihilate('x', 'Y')
+            .annihilate('1', '2');
+        let input: Vec<_> = "axYbYx1xY2Xy".chars().collect();
+        let output: Vec<_> = "abXy".chars().collect();
+        assert_eq!(trigger_all_with(&input, &rules), output);
+    }
+
+    #[test]
+    fn test_trigger_all_with_production() {
+        // a and b make c, which then annihilates with the d before it, and
+        // c and e make f.
+        let rules = PairTable::default()
+            .produce('a', 'b', 'c')
+            .annihilate('d', 'c')
+            .produce('c', 'e', 'f');
+        let input: Vec<_> = "xdabyabez".chars().collect();
+        let output: Vec<_> = "xyfz".chars().collect();
+        assert_eq!(trigger_all_with(&input, &rules), output);
+    }
+
+    #[test]
+    fn test_pair_table_parse() {
+        let rules = PairTable::parse("xY,ab>c").unwrap();
+        assert_eq!(rules.react('x', 'Y'), Some(Reaction::Annihilate));
+        assert_eq!(rules.react('Y', 'x'), Some(Reaction::Annihilate));
+        assert_eq!(rules.react('b', 'a'), Some(Reaction::Produce('c')));
+        assert_eq!(rules.react('x', 'y'), None);
+        assert!(PairTable::parse("xYz").is_err());
+        assert!(PairTable::parse("").is_err());
+    }
+
+    #[test]
+    fn test_trace_with() {
+        let input: Vec<_> = "dabAcCaCBAcCcaDA".chars().collect();
+        let (polymer, steps) = trace_with(&input, &CasePolarity);
+        assert_eq!(polymer, trigger_all(&input));
+        assert_eq!(steps, vec![
+            Step {position: 4, pair: ('c', 'C'), reaction: Reaction::Annihilate},
+            Step {position: 3, pair: ('A', 'a'), reaction: Reaction::Annihilate},
+            Step {position: 6, pair: ('c', 'C'), reaction: Reaction::Annihilate},
+        ]);
+    }
+
+    #[test]
+    fn test_render_trace() {
+        let input: Vec<_> = "dabAcCaCBAcCcaDA".chars().collect();
+        assert_eq!(render_trace(&input, &CasePolarity), "\
+dabAcCaCBAcCcaDA  'cC' at 4 is removed.
+dabAaCBAcCcaDA    'Aa' at 3 is removed.
+dabCBAcCcaDA      'cC' at 6 is removed.
+dabCBAcaDA        No further actions can be taken.
+3 reactions left 10 of 16 units.
+");
+    }
+
+    #[test]
+    fn test_render_trace_production() {
+        let input: Vec<_> = "dabe".chars().collect();
+        let rules = PairTable::parse("ab>c,dc").unwrap();
+        assert_eq!(render_trace(&input, &rules), "\
+dabe  'ab' at 1 becomes 'c'.
+dce   'dc' at 0 is removed.
+e     No further actions can be taken.
+2 reactions left 1 of 4 units.
+");
+    }
+
+    #[test]
+    fn test_render_trace_summary() {
+        let input: Vec<_> = "aA".repeat(30).chars().chain("bBBb".repeat(6).chars()).collect();
+        assert_eq!(render_trace(&input, &CasePolarity), "\
+A/a: 30 reactions
+B/b: 12 reactions
+42 reactions left 0 of 84 units.
+");
+    }
+
+    #[test]
+    fn test_reduce() {
+        assert_eq!(reduce(b"dabAcCaCBAcCcaDA"), b"dabCBAcaDA");
+        assert_eq!(reduce(b"daADCc"), b"");
+        assert_eq!(reduce(b""), b"");
+    }
+
+    #[test]
+    fn test_reduce_matches_trigger_all() {
+        // Every polymer of up to eight units made from a, A, b and B.
+        let units = b"aAbB";
+        for length in 0..9 {
+            for n in 0..4usize.pow(length) {
+                let polymer: Vec<u8> = (0..length)
+                    .map(|i| units[n / 4usize.pow(i) % 4])
+                    .collect();
+                let chars: Vec<char> = polymer.iter().map(|&b| b as char).collect();
+                let expected: Vec<u8> = trigger_all(&chars).iter().map(|&c| c as u8).collect();
+                assert_eq!(reduce(&polymer), expected);
+            }
+        }
+    }
+
+    #[test]
+    fn test_reduce_matches_trigger_all_on_input() {
+        let expected: Vec<u8> = trigger_all(&get_input(Validation::Strict)).iter().map(|&c| c as u8).collect();
+        assert_eq!(reduce(INPUT.trim().as_bytes()), expected);
+    }
+
+    /// Hands out its bytes a few at a time, like a slow file.
+    struct Trickle<'a>(&'a [u8]);
+
+    impl<'a> Read for Trickle<'a> {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            let read = self.0.len().min(buf.len()).min(3);
+            buf[..read].copy_from_slice(&self.0[..read]);
+            self.0 = &self.0[read..];
+            Ok(read)
+        }
+    }
+
+    #[test]
+    fn test_reduce_stream() {
+        let mut output = Vec::new();
+        let result = reduce_stream(&b" dabAcCaCBAcCcaDA\n"[..], Validation::Strict, Some(&mut output));
+        assert_eq!(result.unwrap(), (10, 0));
+        assert_eq!(output, b"dabCBAcaDA\n");
+
+        assert_eq!(reduce_stream(&b""[..], Validation::Strict, None::<Vec<u8>>).unwrap(), (0, 0));
+    }
+
+    /// The example polymer with a digit, a newline and an é in it.
+    const NOISY_EXAMPLE: &[u8] = b"dabA1cC\naCBA\xc3\xa9cCcaDA\n\n";
+
+    #[test]
+    fn test_reduce_stream_strict() {
+        let error = reduce_stream(NOISY_EXAMPLE, Validation::Strict, None::<Vec<u8>>).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+        assert_eq!(error.to_string(), "invalid units at byte offsets 4, 7, 12, 13");
+
+        let many = b"a".iter().chain(b"1".repeat(15).iter()).cloned().collect::<Vec<u8>>();
+        let error = reduce_stream(&many[..], Validation::Strict, None::<Vec<u8>>).unwrap_err();
+        assert_eq!(error.to_string(),
+                   "invalid units at byte offsets 1, 2, 3, 4, 5, 6, 7, 8, 9, 10 and 5 more");
+    }
+
+    #[test]
+    fn test_reduce_stream_sanitise() {
+        let mut output = Vec::new();
+        let result = reduce_stream(NOISY_EXAMPLE, Validation::Sanitise, Some(&mut output));
+        assert_eq!(result.unwrap(), (10, 4));
+        assert_eq!(output, b"dabCBAcaDA\n");
+    }
+
+    #[test]
+    fn test_reduce_stream_across_chunks() {
+        let polymer = random_polymer(3, 5 * CHUNK_SIZE / 2, b"aAbBcC");
+        let mut output = Vec::new();
+        let (length, _) = reduce_stream(&polymer[..], Validation::Strict, Some(&mut output)).unwrap();
+        let mut expected = reduce(&polymer);
+        assert_eq!(length, expected.len());
+        expected.push(b'\n');
+        assert_eq!(output, expected);
+
+        let trickle = || Trickle(b"dabAcC\naCBAcCcaDA");
+        assert_eq!(reduce_stream(trickle(), Validation::Sanitise, None::<Vec<u8>>).unwrap(), (10, 1));
+        assert!(reduce_stream(trickle(), Validation::Strict, None::<Vec<u8>>).is_err());
+    }
+
+    /// A polymer of random units from `units`, the same for every run.
+    fn random_polymer(seed: u64, length: usize, units: &[u8]) -> Vec<u8> {
+        let mut state = seed.wrapping_mul(0x9e37_79b9_7f4a_7c15) | 1;
+        (0..length)
+            .map(|_| {
+                state ^= state << 13;
+                state ^= state >> 7;
+                state ^= state << 17;
+                units[(state % units.len() as u64) as usize]
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_merge() {
+        assert_eq!(merge(b"dabA".to_vec(), b"aBcD".to_vec()), b"dacD");
+        assert_eq!(merge(b"ab".to_vec(), b"BA".to_vec()), b"");
+        assert_eq!(merge(Vec::new(), b"aB".to_vec()), b"aB");
+        assert_eq!(merge(b"aB".to_vec(), Vec::new()), b"aB");
+    }
+
+    #[test]
+    fn test_reduce_in_chunks_matches_trigger_all() {
+        for seed in 0..20 {
+            for &units in [&b"aA"[..], b"aAbB", b"aAbBcCdDeE"].iter() {
+                let polymer = random_polymer(seed, 1 + seed as usize * 97, units);
+                let chars: Vec<char> = polymer.iter().map(|&b| b as char).collect();
+                let expected: Vec<u8> = trigger_all(&chars).iter().map(|&c| c as u8).collect();
+                for &chunks in [1, 2, 3, 7, 64].iter() {
+                    assert_eq!(reduce_in_chunks(&polymer, chunks), expected,
+                               "seed {} with {} chunks", seed, chunks);
+                }
+                assert_eq!(parallel_reduce(&polymer), expected);
+            }
+        }
+    }
+
+    #[test]
+    fn test_reduce_in_chunks_empty() {
+        assert_eq!(reduce_in_chunks(b"", 4), b"");
+    }
+
+    #[test]
+    fn test_removal_lengths() {
+        let lengths = removal_lengths(&reduce(b"dabAcCaCBAcCcaDA"));
+        assert_eq!(lengths.len(), 26);
+        assert_eq!(&lengths[..5], &[('A', 6), ('B', 8), ('C', 4), ('D', 6), ('E', 10)]);
+        assert_eq!(lengths[25], ('Z', 10));
+    }
+
+    #[test]
+    fn test_removal_lengths_match_original_polymer() {
+        let polymer = INPUT.trim().as_bytes();
+        let lengths = removal_lengths(&reduce(polymer));
+        for (unit, length) in lengths {
+            let without_unit: Vec<u8> = polymer.iter()
+                .cloned()
+                .filter(|&c| c.to_ascii_uppercase() != unit as u8)
+                .collect();
+            assert_eq!(reduce(&without_unit).len(), length);
+        }
+    }
+
+    /// Every way of removing up to `k` types, tried one by one, picking the
+    /// shortest result, then the fewest types, then the first alphabetically.
+    fn brute_force_removal(polymer: &[char], k: usize) -> (Vec<char>, usize) {
+        let mut types: Vec<char> = polymer.iter().map(|c| c.to_ascii_uppercase()).collect();
+        types.sort();
+        types.dedup();
+        let mut best = (trigger_all(&polymer.to_vec()).len(), Vec::new());
+        for subset in 1..1usize << types.len() {
+            if subset.count_ones() as usize > k {
+                continue;
+            }
+            let removed: Vec<char> = types.iter()
+                .enumerate()
+                .filter(|(i, _)| subset & (1 << i) != 0)
+                .map(|(_, &unit)| unit)
+                .collect();
+            let without_types = polymer.iter()
+                .cloned()
+                .filter(|c| !removed.contains(&c.to_ascii_uppercase()))
+                .collect();
+            let length = trigger_all(&without_types).len();
+            if (length, removed.len(), &removed) < (best.0, best.1.len(), &best.1) {
+                best = (length, removed);
+            }
+        }
+        (best.1, best.0)
+    }
+
+    #[test]
+    fn test_best_removal() {
+        let input = b"dabAcCaCBAcCcaDA";
+        assert_eq!(best_removal(input, 0), (vec![], 10));
+        assert_eq!(best_removal(input, 1), (vec!['C'], 4));
+        assert_eq!(best_removal(input, 2), (vec!['A', 'B'], 0));
+        assert_eq!(best_removal(input, 4), (vec!['A', 'B'], 0));
+        // Removing A and C leaves nothing, but so does removing just C.
+        assert_eq!(best_removal(b"aCA", 2), (vec!['C'], 0));
+    }
+
+    #[test]
+    fn test_best_removal_matches_brute_force() {
+        for seed in 0..30 {
+            let polymer = random_polymer(seed, 40, b"aAbBcCdDeE");
+            let chars: Vec<char> = polymer.iter().map(|&b| b as char).collect();
+            for k in 0..4 {
+                assert_eq!(best_removal(&polymer, k), brute_force_removal(&chars, k),
+                           "seed {} k {}", seed, k);
+            }
+        }
+    }
+
+    #[test]
+    fn test_removal_lower_bound() {
+        let input = b"aaBcDdD";
+        // Two more a than A, and one unit apart for B, C and D.
+        assert_eq!(removal_lower_bound(input, 0), 5);
+        assert_eq!(removal_lower_bound(input, 1), 3);
+        assert_eq!(removal_lower_bound(input, 2), 2);
+        assert_eq!(removal_lower_bound(input, 4), 0);
+    }
+
+    #[test]
+    fn test_validate_polymer() {
+        assert_eq!(validate_polymer("dabAcCaCBAcCcaDA\n"), vec![]);
+        assert_eq!(validate_polymer("  ab1A\nB\u{e9}c.\n"), vec![
+            InvalidUnit {position: 4, character: '1'},
+            InvalidUnit {position: 6, character: '\n'},
+            InvalidUnit {position: 8, character: '\u{e9}'},
+            InvalidUnit {position: 10, character: '.'},
+        ]);
+    }
+
+    #[test]
+    fn test_parse_polymer_strict() {
+        let (polymer, dropped) = parse_polymer("aBc\n", Validation::Strict).unwrap();
+        assert_eq!(polymer, vec!['a', 'B', 'c']);
+        assert_eq!(dropped, vec![]);
+
+        assert_eq!(parse_polymer("aB 3c", Validation::Strict), Err(vec![
+            InvalidUnit {position: 2, character: ' '},
+            InvalidUnit {position: 3, character: '3'},
+        ]));
+    }
+
+    #[test]
+    fn test_parse_polymer_sanitise() {
+        let (polymer, dropped) = parse_polymer("da1AD\u{e9}Cc\n", Validation::Sanitise).unwrap();
+        assert_eq!(polymer, vec!['d', 'a', 'A', 'D', 'C', 'c']);
+        assert_eq!(dropped, vec![
+            InvalidUnit {position: 2, character: '1'},
+            InvalidUnit {position: 5, character: '\u{e9}'},
+        ]);
+        assert_eq!(trigger_all(&polymer), vec![]);
+    }
+
+    #[test]
+    fn test_input_is_valid() {
+        assert_eq!(validate_polymer(INPUT), vec![]);
+    }
+
+    #[test]
+    fn test_reacting_bytes() {
+        assert!(reacting_bytes(b'a', b'A'));
+        assert!(reacting_bytes(b'Z', b'z'));
+        assert!(!reacting_bytes(b'a', b'a'));
+        assert!(!reacting_bytes(b'a', b'B'));
+        // '@' and '`' also differ by the case bit, but aren't units.
+        assert!(!reacting_bytes(b'@', b'`'));
+    }
+
     #[test]
     fn test_reacting_different_letters_capitals() {
         let a = 'C';
@@ -133,25 +133,12 @@ struct Bounds {
 }
 
 
-pub fn run() {
+pub fn run(_options: &[String]) {
     let points = parse_input(include_str!("../input/day_06.txt"));
 
     let bounds = create_bounds(&points);
-    let grid = create_grid(&points, &bounds);
-
-    let mut areas = HashMap::new();
-    let mut infinite_areas = HashSet::new();
-    for (point, area_number) in grid.iter() {
-        if on_bounds(point,&bounds) {
-            infinite_areas.insert(*area_number);
-            areas.remove(area_number);
-        }
-        if !infinite_areas.contains(area_number) {
-            *areas.entry(area_number).or_insert(0) += 1;
-        }
-    }
-
-    let biggest_area_size = areas.values()
+    let biggest_area_size = area_sizes(&points).iter()
+        .filter_map(|&size| size)
         .max()
         .unwrap();
     println!("The biggest non-infinite area size is: {}", biggest_area_size);
@@ -175,6 +162,49 @@ fn create_grid(points: &Vec<Point>, bounds: &Bounds) -> Grid {
     grid
 }
 
+/// The size of each coordinate's area, in the same order as `points`, or `None`
+/// if the area is infinite.
+fn area_sizes(points: &Vec<Point>) -> Vec<Option<usize>> {
+    let bounds = create_bounds(points);
+    let grid = create_grid(points, &bounds);
+    let infinite = infinite_areas(points, &bounds);
+
+    let mut sizes = vec![0; points.len()];
+    for &area_number in grid.values() {
+        sizes[area_number] += 1;
+    }
+    sizes.into_iter()
+        .enumerate()
+        .map(|(area_number, size)| if infinite.contains(&area_number) { None } else { Some(size) })
+        .collect()
+}
+
+/// Finds the areas that go on forever, by looking at who owns the locations
+/// just outside the bounds. Every coordinate is inside the bounds, so stepping
+/// further out from there adds one to the distance to all of them, and the
+/// closest coordinate stays the closest all the way out. Going the other way,
+/// any location outside the bounds can be walked back to one just outside them
+/// the same way, so an area that doesn't own any of those is finite.
+fn infinite_areas(points: &Vec<Point>, bounds: &Bounds) -> HashSet<usize> {
+    let outside = Bounds {
+        x: Range {min: bounds.x.min - 1, max: bounds.x.max + 1},
+        y: Range {min: bounds.y.min - 1, max: bounds.y.max + 1},
+    };
+    let mut infinite = HashSet::new();
+    for x in outside.x.min..=outside.x.max {
+        for y in outside.y.min..=outside.y.max {
+            let point = (x, y);
+            if !on_bounds(&point, &outside) {
+                continue;
+            }
+            if let Some(area_number) = closest_point(&point, points) {
+                infinite.insert(area_number);
+            }
+        }
+    }
+    infinite
+}
+
 fn count_points_below(points: &Vec<Point>, bounds: &Bounds, treshold: i32) -> i32 {
     let mut count = 0;
     for x in bounds.x.min..=bounds.x.max {
@@ -294,6 +324,46 @@ mod tests {
         assert_eq!(parse_input(input), output);
     }
 
+    const EXAMPLE: &str =
+        "1, 1\n\
+        1, 6\n\
+        8, 3\n\
+        3, 4\n\
+        5, 5\n\
+        8, 9";
+
+    #[test]
+    fn test_area_sizes() {
+        let points = parse_input(EXAMPLE);
+        assert_eq!(area_sizes(&points), vec![None, None, None, Some(9), Some(17), None]);
+    }
+
+    #[test]
+    fn test_infinite_areas() {
+        let points = parse_input(EXAMPLE);
+        let bounds = create_bounds(&points);
+        let output: HashSet<usize> = [0, 1, 2, 5].iter().cloned().collect();
+        assert_eq!(infinite_areas(&points, &bounds), output);
+    }
+
+    #[test]
+    fn test_area_sizes_enclosed() {
+        // The middle coordinate is boxed in by the other four, which all go on
+        // forever.
+        let points: Vec<Point> = vec![
+            (0, 3),
+            (3, 0),
+            (6, 3),
+            (3, 6),
+            (3, 3)];
+        assert_eq!(area_sizes(&points), vec![None, None, None, None, Some(9)]);
+    }
+
+    #[test]
+    fn test_area_sizes_single_point() {
+        assert_eq!(area_sizes(&vec![(4, 2)]), vec![None]);
+    }
+
     #[test]
     fn test_create_bounds() {
         let input: Vec<Point> = vec![
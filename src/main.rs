@@ -7,16 +7,24 @@ use std::env;
 use std::collections::HashMap;
 
 fn main() {
-    let mut args = env::args();
-    args.next();
-
     let modules = create_modules();
-    while let Some(arg) = args.next() {
-        modules.run(&arg);
+
+    // Every argument that isn't a module name is an option for the module
+    // preceding it, e.g. `day_03 render fabric.ppm day_04`.
+    let mut invocations: Vec<(String, Vec<String>)> = Vec::new();
+    for arg in env::args().skip(1) {
+        match invocations.last_mut() {
+            Some((_, options)) if !modules.has_module(&arg) => options.push(arg),
+            _ => invocations.push((arg, Vec::new())),
+        }
+    }
+
+    for (name, options) in invocations.iter() {
+        modules.run(name, options);
     }
 }
 
-type Run = fn();
+type Run = fn(&[String]);
 
 struct Modules {
     runners: HashMap<String, Run>,
@@ -27,9 +35,13 @@ impl Modules {
         self.runners.insert(name, func);
     }
 
-    fn run(&self, name: &String) {
+    fn has_module(&self, name: &str) -> bool {
+        self.runners.contains_key(name)
+    }
+
+    fn run(&self, name: &str, options: &[String]) {
         let runner = self.runners.get(name).unwrap();
-        runner();
+        runner(options);
     }
 }
 